@@ -0,0 +1,298 @@
+//Data augmentation
+
+//Random distortions of the training images, applied afresh to every batch so each epoch sees slightly different
+//images. Each augmentation in the pipeline fires independently with its own probability. The geometric ones resample
+//the 28x28 image bilinearly, treating anything outside it as background
+
+use std::str::FromStr;
+use ndarray::{s, Array1, Array2};
+use ndarray_rand::rand_distr::StandardNormal;
+use rand::{Rng, RngCore};
+use crate::mnist::{MnistDataset, MnistImage, IMAGE_COLUMNS, IMAGE_ROWS};
+
+pub trait Augmentation {
+    //Distorts a rows x columns image of pixels in [0, 1]
+    fn apply(&self, image: &Array2<f64>, rng: &mut dyn RngCore) -> Array2<f64>;
+}
+
+//Bilinear interpolation between the four pixels around a fractional position
+fn sample(image: &Array2<f64>, row: f64, column: f64) -> f64 {
+    let (rows, columns) = image.dim();
+    let pixel = |r: f64, c: f64| {
+        if r < 0.0 || c < 0.0 || r >= rows as f64 || c >= columns as f64 { 0.0 } else { image[(r as usize, c as usize)] }
+    };
+
+    let (r0, c0) = (row.floor(), column.floor());
+    let (fr, fc) = (row - r0, column - c0);
+    pixel(r0, c0) * (1.0 - fr) * (1.0 - fc)
+        + pixel(r0, c0 + 1.0) * (1.0 - fr) * fc
+        + pixel(r0 + 1.0, c0) * fr * (1.0 - fc)
+        + pixel(r0 + 1.0, c0 + 1.0) * fr * fc
+}
+
+//Builds an image the same size, where each pixel is sampled from the source position source_of gives for it
+fn remap(image: &Array2<f64>, source_of: impl Fn(f64, f64) -> (f64, f64)) -> Array2<f64> {
+    Array2::from_shape_fn(image.dim(), |(row, column)| {
+        let (source_row, source_column) = source_of(row as f64, column as f64);
+        sample(image, source_row, source_column)
+    })
+}
+
+fn centre(image: &Array2<f64>) -> (f64, f64) {
+    let (rows, columns) = image.dim();
+    ((rows as f64 - 1.0) / 2.0, (columns as f64 - 1.0) / 2.0)
+}
+
+//Moves the image by a whole number of pixels up to max_pixels in each direction
+pub struct Shift {
+    pub max_pixels: usize,
+}
+
+impl Augmentation for Shift {
+    fn apply(&self, image: &Array2<f64>, rng: &mut dyn RngCore) -> Array2<f64> {
+        let max = self.max_pixels as i64;
+        let rows = rng.gen_range(-max..=max) as f64;
+        let columns = rng.gen_range(-max..=max) as f64;
+        remap(image, |row, column| (row - rows, column - columns))
+    }
+}
+
+//Rotates about the centre by up to max_degrees either way
+pub struct Rotate {
+    pub max_degrees: f64,
+}
+
+impl Augmentation for Rotate {
+    fn apply(&self, image: &Array2<f64>, rng: &mut dyn RngCore) -> Array2<f64> {
+        let angle = rng.gen_range(-self.max_degrees..=self.max_degrees).to_radians();
+        let (sin, cos) = angle.sin_cos();
+        let (centre_row, centre_column) = centre(image);
+        remap(image, |row, column| {
+            let (y, x) = (row - centre_row, column - centre_column);
+            (centre_row + cos * y - sin * x, centre_column + sin * y + cos * x)
+        })
+    }
+}
+
+//Zooms about the centre by a factor within max_change of 1
+pub struct Scale {
+    pub max_change: f64,
+}
+
+impl Augmentation for Scale {
+    fn apply(&self, image: &Array2<f64>, rng: &mut dyn RngCore) -> Array2<f64> {
+        let factor = rng.gen_range(1.0 - self.max_change..=1.0 + self.max_change);
+        let (centre_row, centre_column) = centre(image);
+        remap(image, |row, column| (centre_row + (row - centre_row) / factor, centre_column + (column - centre_column) / factor))
+    }
+}
+
+//Simard et al's elastic distortion: a random displacement field smoothed by a gaussian of width sigma, then scaled by alpha
+pub struct Elastic {
+    pub alpha: f64,
+    pub sigma: f64,
+}
+
+impl Elastic {
+    fn kernel(&self) -> Array1<f64> {
+        let radius = (3.0 * self.sigma).ceil() as i64;
+        let kernel = Array1::from_iter((-radius..=radius).map(|x| (-(x * x) as f64 / (2.0 * self.sigma * self.sigma)).exp()));
+        let sum = kernel.sum();
+        kernel / sum
+    }
+
+    //Separable gaussian blur, rows then columns, treating outside the field as zero displacement
+    fn smooth(field: &Array2<f64>, kernel: &Array1<f64>) -> Array2<f64> {
+        let (rows, columns) = field.dim();
+        let radius = (kernel.len() / 2) as i64;
+        let convolve = |field: &Array2<f64>, along_rows: bool| Array2::from_shape_fn((rows, columns), |(row, column)| {
+            kernel.iter().enumerate().map(|(k, &weight)| {
+                let offset = k as i64 - radius;
+                let (r, c) = if along_rows { (row as i64 + offset, column as i64) } else { (row as i64, column as i64 + offset) };
+                if r < 0 || c < 0 || r >= rows as i64 || c >= columns as i64 { 0.0 } else { weight * field[(r as usize, c as usize)] }
+            }).sum()
+        });
+        convolve(&convolve(field, true), false)
+    }
+}
+
+impl Augmentation for Elastic {
+    fn apply(&self, image: &Array2<f64>, rng: &mut dyn RngCore) -> Array2<f64> {
+        let kernel = self.kernel();
+        let mut displacement = || {
+            let field = Array2::from_shape_fn(image.dim(), |_| rng.gen_range(-1.0..=1.0));
+            Elastic::smooth(&field, &kernel) * self.alpha
+        };
+        let rows = displacement();
+        let columns = displacement();
+        remap(image, |row, column| {
+            let index = (row as usize, column as usize);
+            (row + rows[index], column + columns[index])
+        })
+    }
+}
+
+//Adds gaussian noise of standard deviation std_dev to every pixel, clamped back into [0, 1]
+pub struct Noise {
+    pub std_dev: f64,
+}
+
+impl Augmentation for Noise {
+    fn apply(&self, image: &Array2<f64>, rng: &mut dyn RngCore) -> Array2<f64> {
+        image.mapv(|pixel| {
+            let noise: f64 = rng.sample(StandardNormal);
+            (pixel + self.std_dev * noise).clamp(0.0, 1.0)
+        })
+    }
+}
+
+//Blanks a random rectangle of between 2% and max_area of the image to background, with an aspect ratio between 1:3 and 3:1
+pub struct Erase {
+    pub max_area: f64,
+}
+
+impl Augmentation for Erase {
+    fn apply(&self, image: &Array2<f64>, rng: &mut dyn RngCore) -> Array2<f64> {
+        let (rows, columns) = image.dim();
+        let area = rng.gen_range(0.02..=self.max_area.max(0.02)) * (rows * columns) as f64;
+        let aspect_ratio = rng.gen_range((1.0f64 / 3.0).ln()..=3.0f64.ln()).exp();
+        let height = ((area * aspect_ratio).sqrt().round() as usize).clamp(1, rows);
+        let width = ((area / aspect_ratio).sqrt().round() as usize).clamp(1, columns);
+        let top = rng.gen_range(0..=rows - height);
+        let left = rng.gen_range(0..=columns - width);
+
+        let mut image = image.clone();
+        image.slice_mut(s![top..top + height, left..left + width]).fill(0.0);
+        image
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AugmentationKind {
+    Shift { max_pixels: usize },
+    Rotate { max_degrees: f64 },
+    Scale { max_change: f64 },
+    Elastic { alpha: f64, sigma: f64 },
+    Noise { std_dev: f64 },
+    Erase { max_area: f64 },
+}
+
+impl AugmentationKind {
+    pub fn build(self) -> Box<dyn Augmentation> {
+        match self {
+            AugmentationKind::Shift { max_pixels } => Box::new(Shift { max_pixels }),
+            AugmentationKind::Rotate { max_degrees } => Box::new(Rotate { max_degrees }),
+            AugmentationKind::Scale { max_change } => Box::new(Scale { max_change }),
+            AugmentationKind::Elastic { alpha, sigma } => Box::new(Elastic { alpha, sigma }),
+            AugmentationKind::Noise { std_dev } => Box::new(Noise { std_dev }),
+            AugmentationKind::Erase { max_area } => Box::new(Erase { max_area }),
+        }
+    }
+}
+
+//One augmentation and how often it fires
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AugmentationStep {
+    pub kind: AugmentationKind,
+    pub probability: f64,
+}
+
+//Parses name[:parameter[:parameter]][@probability], e.g. rotate:10@0.5. Parameters and probability are optional:
+//shift:2, rotate:15, scale:0.1, elastic:34:4, noise:0.1 and erase:0.2 by default, always applied
+impl FromStr for AugmentationStep {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (spec, probability) = match s.split_once('@') {
+            Some((spec, probability)) => (spec, probability.parse::<f64>().map_err(|e| format!("invalid probability {}: {}", probability, e))?),
+            None => (s, 1.0),
+        };
+        if !(0.0..=1.0).contains(&probability) {
+            return Err(format!("probability {} is not between 0 and 1", probability));
+        }
+
+        let mut parts = spec.split(':');
+        let name = parts.next().unwrap_or_default();
+        let parameters = parts.map(|p| p.parse::<f64>().map_err(|e| format!("invalid parameter {} of {}: {}", p, name, e))).collect::<Result<Vec<f64>, String>>()?;
+        let parameter = |index: usize, default: f64| parameters.get(index).copied().unwrap_or(default);
+        let max_parameters = if name == "elastic" { 2 } else { 1 };
+        if parameters.len() > max_parameters {
+            return Err(format!("{} takes at most {} parameters", name, max_parameters));
+        }
+
+        let kind = match name {
+            "shift" => AugmentationKind::Shift { max_pixels: parameter(0, 2.0) as usize },
+            "rotate" => AugmentationKind::Rotate { max_degrees: parameter(0, 15.0) },
+            "scale" => AugmentationKind::Scale { max_change: parameter(0, 0.1) },
+            "elastic" => AugmentationKind::Elastic { alpha: parameter(0, 34.0), sigma: parameter(1, 4.0) },
+            "noise" => AugmentationKind::Noise { std_dev: parameter(0, 0.1) },
+            "erase" => AugmentationKind::Erase { max_area: parameter(0, 0.2) },
+            _ => return Err(format!("unknown augmentation {}, expected shift, rotate, scale, elastic, noise or erase", name)),
+        };
+
+        //Neither lets NaN or infinity through
+        let non_negative = |value: f64| (0.0..=f64::MAX).contains(&value);
+        let positive = |value: f64| value > 0.0 && value <= f64::MAX;
+        let requirement = match kind {
+            AugmentationKind::Shift { .. } if !non_negative(parameter(0, 2.0)) => Some("pixels must be at least 0"),
+            AugmentationKind::Rotate { max_degrees } if !non_negative(max_degrees) => Some("degrees must be at least 0"),
+            AugmentationKind::Scale { max_change } if !(0.0..1.0).contains(&max_change) => Some("max_change must be at least 0 and less than 1"),
+            AugmentationKind::Elastic { alpha, sigma } if !(alpha.is_finite() && positive(sigma)) => Some("alpha must be finite and sigma greater than 0"),
+            AugmentationKind::Noise { std_dev } if !non_negative(std_dev) => Some("std_dev must be at least 0"),
+            AugmentationKind::Erase { max_area } if !(max_area > 0.0 && max_area <= 1.0) => Some("max_area must be greater than 0 and at most 1"),
+            _ => None,
+        };
+        if let Some(requirement) = requirement {
+            return Err(format!("invalid {}: {}", spec, requirement));
+        }
+        Ok(AugmentationStep { kind, probability })
+    }
+}
+
+//Applies each augmentation in order, each independently with its own probability
+#[derive(Default)]
+pub struct Augmentations {
+    steps: Vec<(f64, Box<dyn Augmentation>)>,
+}
+
+impl Augmentations {
+    pub fn new(steps: &[AugmentationStep]) -> Augmentations {
+        Augmentations { steps: steps.iter().map(|step| (step.probability, step.kind.build())).collect() }
+    }
+
+    pub fn apply(&self, image: &mut MnistImage, rng: &mut dyn RngCore) {
+        if self.steps.is_empty() {
+            return;
+        }
+
+        let size = image.image.len();
+        let mut pixels = std::mem::replace(&mut image.image, Array2::zeros((0, 0))).into_shape((IMAGE_ROWS, IMAGE_COLUMNS)).unwrap();
+        for (probability, augmentation) in &self.steps {
+            if rng.gen_bool(*probability) {
+                pixels = augmentation.apply(&pixels, rng);
+            }
+        }
+        image.image = pixels.into_shape((size, 1)).unwrap();
+    }
+}
+
+//The dataset followed by copies augmented versions of it, each drawn afresh from the augmentations
+pub fn expand(dataset: &MnistDataset, augmentations: &Augmentations, copies: usize, rng: &mut dyn RngCore) -> MnistDataset {
+    let image_size = IMAGE_ROWS * IMAGE_COLUMNS;
+    let mut pixels = Vec::with_capacity(dataset.len() * (copies + 1) * image_size);
+    let mut labels = Vec::with_capacity(dataset.len() * (copies + 1));
+
+    pixels.extend(dataset.pixels().iter());
+    labels.extend_from_slice(dataset.labels());
+    for _ in 0..copies {
+        for mut image in dataset.iter() {
+            augmentations.apply(&mut image, rng);
+            pixels.extend(image.image.iter().map(|&pixel| (pixel * 255.0).round().clamp(0.0, 255.0) as u8));
+            labels.push(image.label);
+        }
+    }
+
+    let pixels = Array2::from_shape_vec((labels.len(), image_size), pixels).unwrap();
+    MnistDataset::new(pixels, labels, dataset.num_classes())
+}
+
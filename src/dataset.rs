@@ -0,0 +1,298 @@
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use clap::{Args, ValueEnum};
+use serde::{Deserialize, Serialize};
+use flate2::read::GzDecoder;
+use sha2::{Digest, Sha256};
+use crate::idx::GZIP_MAGIC;
+use crate::mnist::{load_mnist_file, MnistError, MnistDataset, MnistFormat};
+
+//SHA-256 of the gzipped MNIST files as distributed, in the order training images, training labels, test images, test labels
+const MNIST_SHA256: [&str; 4] = [
+    "440fcabf73cc546fa21475e81ea370265605f56be210a4024d2ca8f203523609",
+    "3552534a0a558bbed6aed32b30c495cca23d567ec52cac8be1a0730e8010255c",
+    "8d422c7b0a1c1c79245a5bcf07fe86e33eeafee792b84584aec276f5a2dbc4e6",
+    "f7ae60f92e00ec6debd23a6088c31dbd2371eca3ffa0defaefb259924204aec6",
+];
+
+//SHA-256 of the same files decompressed, for copies decompressed or recompressed since. That of the training images
+//isn't recorded, so only the distributed gzip of them can be verified
+const MNIST_DECOMPRESSED_SHA256: [Option<&str>; 4] = [
+    None,
+    Some("65a50cbbf4e906d70832878ad85ccda5333a97f0f4c3dd2ef09a8a9eef7101c5"),
+    Some("0fa7898d509279e482958e8ce81c8e77db3f2f8254e26661ceb7762c4d494ce7"),
+    Some("ff7bcfd416de33731a308c3f266cc351222c34898ecbeaf847f06e48f7ec33f2"),
+];
+
+const DIGITS: &str = "0123456789";
+const UPPERCASE: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const LOWERCASE: &str = "abcdefghijklmnopqrstuvwxyz";
+//The lowercase letters the balanced and bymerge splits keep apart from their uppercase, the rest look alike and are merged
+const UNMERGED_LOWERCASE: &str = "abdefghnqrt";
+
+const FASHION_CLASSES: [&str; 10] = ["T-shirt/top", "Trouser", "Pullover", "Dress", "Coat", "Sandal", "Shirt", "Sneaker", "Bag", "Ankle boot"];
+//Romanised hiragana
+const KMNIST_CLASSES: [&str; 10] = ["o", "ki", "su", "tsu", "na", "ha", "ma", "ya", "re", "wo"];
+
+//Every dataset distributed in the MNIST file layout of 28x28 u8 images
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum DatasetKind {
+    #[default]
+    Mnist,
+    FashionMnist,
+    Kmnist,
+    EmnistBalanced,
+    EmnistByclass,
+    EmnistBymerge,
+    EmnistDigits,
+    EmnistLetters,
+    EmnistMnist,
+}
+
+impl DatasetKind {
+    pub fn format(&self) -> MnistFormat {
+        match self {
+            DatasetKind::Mnist | DatasetKind::FashionMnist | DatasetKind::Kmnist => MnistFormat::MNIST,
+            DatasetKind::EmnistLetters => MnistFormat { num_classes: 26, first_label: 1, transposed: true },
+            _ => MnistFormat { num_classes: self.class_names().len(), first_label: 0, transposed: true },
+        }
+    }
+
+    pub fn num_classes(&self) -> usize {
+        self.format().num_classes
+    }
+
+    //Indexed by label
+    pub fn class_names(&self) -> Vec<String> {
+        let chars = |classes: &[&str]| classes.concat().chars().map(String::from).collect();
+        match self {
+            DatasetKind::Mnist | DatasetKind::EmnistDigits | DatasetKind::EmnistMnist => chars(&[DIGITS]),
+            DatasetKind::FashionMnist => FASHION_CLASSES.iter().map(|name| name.to_string()).collect(),
+            DatasetKind::Kmnist => KMNIST_CLASSES.iter().map(|name| name.to_string()).collect(),
+            DatasetKind::EmnistBalanced | DatasetKind::EmnistBymerge => chars(&[DIGITS, UPPERCASE, UNMERGED_LOWERCASE]),
+            DatasetKind::EmnistByclass => chars(&[DIGITS, UPPERCASE, LOWERCASE]),
+            DatasetKind::EmnistLetters => chars(&[UPPERCASE]),
+        }
+    }
+
+    //Fashion-MNIST and KMNIST reuse the MNIST file names, EMNIST prefixes them with its split
+    fn file_name(&self, file_name: &str) -> String {
+        let split = match self {
+            DatasetKind::Mnist | DatasetKind::FashionMnist | DatasetKind::Kmnist => return file_name.to_string(),
+            DatasetKind::EmnistBalanced => "balanced",
+            DatasetKind::EmnistByclass => "byclass",
+            DatasetKind::EmnistBymerge => "bymerge",
+            DatasetKind::EmnistDigits => "digits",
+            DatasetKind::EmnistLetters => "letters",
+            DatasetKind::EmnistMnist => "mnist",
+        };
+        format!("emnist-{}-{}", split, file_name.replace("t10k", "test"))
+    }
+
+    //Number of training and test images
+    fn num_images(&self) -> (usize, usize) {
+        match self {
+            DatasetKind::Mnist | DatasetKind::FashionMnist | DatasetKind::Kmnist | DatasetKind::EmnistMnist => (60000, 10000),
+            DatasetKind::EmnistBalanced => (112800, 18800),
+            DatasetKind::EmnistByclass | DatasetKind::EmnistBymerge => (697932, 116323),
+            DatasetKind::EmnistDigits => (240000, 40000),
+            DatasetKind::EmnistLetters => (124800, 20800),
+        }
+    }
+
+    fn sha256(&self) -> Option<[&'static str; 4]> {
+        match self {
+            DatasetKind::Mnist => Some(MNIST_SHA256),
+            _ => None,
+        }
+    }
+
+    fn decompressed_sha256(&self) -> [Option<&'static str>; 4] {
+        match self {
+            DatasetKind::Mnist => MNIST_DECOMPRESSED_SHA256,
+            _ => [None; 4],
+        }
+    }
+}
+
+//As named on the command line
+impl Display for DatasetKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_possible_value().unwrap().get_name())
+    }
+}
+
+//Which dataset to use and where its four files are. Each file defaults to its distributed name inside the data directory
+#[derive(Args, Clone, Debug)]
+pub struct DatasetPaths {
+    #[arg(long = "dataset", global = true, help = "Which dataset the files hold. Defaults to mnist when training, or to the dataset a loaded network was trained on")]
+    pub kind: Option<DatasetKind>,
+
+    #[arg(long, global = true, env = "MNIST_DATA_DIR", default_value = ".", help = "Directory containing the dataset files")]
+    pub data_dir: PathBuf,
+
+    #[arg(long, global = true, help = "Training images file, overriding the one in --data-dir. - reads from stdin")]
+    pub training_images: Option<PathBuf>,
+
+    #[arg(long, global = true, help = "Training labels file, overriding the one in --data-dir. - reads from stdin")]
+    pub training_labels: Option<PathBuf>,
+
+    #[arg(long, global = true, help = "Test images file, overriding the one in --data-dir. - reads from stdin")]
+    pub testing_images: Option<PathBuf>,
+
+    #[arg(long, global = true, help = "Test labels file, overriding the one in --data-dir. - reads from stdin")]
+    pub testing_labels: Option<PathBuf>,
+}
+
+impl DatasetPaths {
+    pub fn kind(&self) -> DatasetKind {
+        self.kind.unwrap_or_default()
+    }
+
+    fn path(&self, path: &Option<PathBuf>, default_file_name: &str) -> String {
+        match path {
+            Some(path) => path.to_string_lossy().into_owned(),
+            None => self.data_dir.join(self.kind().file_name(default_file_name)).to_string_lossy().into_owned(),
+        }
+    }
+
+    pub fn training_images(&self) -> String {
+        self.path(&self.training_images, "train-images-idx3-ubyte.gz")
+    }
+
+    pub fn training_labels(&self) -> String {
+        self.path(&self.training_labels, "train-labels-idx1-ubyte.gz")
+    }
+
+    pub fn testing_images(&self) -> String {
+        self.path(&self.testing_images, "t10k-images-idx3-ubyte.gz")
+    }
+
+    pub fn testing_labels(&self) -> String {
+        self.path(&self.testing_labels, "t10k-labels-idx1-ubyte.gz")
+    }
+
+    pub fn load_training(&self) -> Result<MnistDataset, MnistError> {
+        load_mnist_file(&self.training_images(), &self.training_labels(), self.kind().format())
+    }
+
+    pub fn load_testing(&self) -> Result<MnistDataset, MnistError> {
+        load_mnist_file(&self.testing_images(), &self.testing_labels(), self.kind().format())
+    }
+}
+
+fn sha256(mut reader: impl Read) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn sha256_file(file_name: &str) -> std::io::Result<String> {
+    sha256(BufReader::new(File::open(file_name)?))
+}
+
+//Of the contents, decompressing them first if the file is gzipped
+fn sha256_decompressed(file_name: &str) -> std::io::Result<String> {
+    let reader = BufReader::new(File::open(file_name)?);
+    if is_gzip(file_name) {
+        sha256(GzDecoder::new(reader))
+    } else {
+        sha256(reader)
+    }
+}
+
+fn is_gzip(file_name: &str) -> bool {
+    let mut prefix = Vec::with_capacity(GZIP_MAGIC.len());
+    match File::open(file_name) {
+        Ok(f) => f.take(GZIP_MAGIC.len() as u64).read_to_end(&mut prefix).is_ok() && prefix == GZIP_MAGIC,
+        Err(_) => false,
+    }
+}
+
+//Checks one file exists and matches its known checksum, printing the outcome. Files that aren't the distributed gzip
+//are checked by their decompressed contents instead. Files of datasets without known checksums only need to exist
+fn verify_file(description: &str, file_name: &str, expected_sha256: Option<&str>, expected_decompressed_sha256: Option<&str>) -> bool {
+    if !Path::new(file_name).is_file() {
+        println!("{} {}: missing", description, file_name);
+        return false;
+    }
+    let Some(expected_sha256) = expected_sha256 else {
+        println!("{} {}: present, no known checksum", description, file_name);
+        return true;
+    };
+
+    match sha256_file(file_name) {
+        Ok(sha256) if sha256 == expected_sha256 => {
+            println!("{} {}: checksum ok", description, file_name);
+            return true;
+        },
+        Ok(_) => {},
+        Err(e) => {
+            println!("{} {}: could not read: {}", description, file_name, e);
+            return false;
+        },
+    }
+
+    let Some(expected_decompressed_sha256) = expected_decompressed_sha256 else {
+        println!("{} {}: checksum mismatch, expected the distributed gzip with checksum {}", description, file_name, expected_sha256);
+        return false;
+    };
+    match sha256_decompressed(file_name) {
+        Ok(sha256) if sha256 == expected_decompressed_sha256 => {
+            println!("{} {}: decompressed checksum ok", description, file_name);
+            true
+        },
+        Ok(sha256) => {
+            println!("{} {}: checksum mismatch, expected {} decompressed but found {}", description, file_name, expected_decompressed_sha256, sha256);
+            false
+        },
+        Err(e) => {
+            println!("{} {}: could not decompress: {}", description, file_name, e);
+            false
+        },
+    }
+}
+
+//Parses an image and label file pair and checks how many images they hold, printing the outcome
+fn verify_set(description: &str, images: Result<MnistDataset, MnistError>, expected_count: usize) -> bool {
+    match images {
+        Ok(images) if images.len() == expected_count => {
+            println!("{} set: {} images ok", description, images.len());
+            true
+        },
+        Ok(images) => {
+            println!("{} set: {} images, expected {}", description, images.len(), expected_count);
+            false
+        },
+        Err(e) => {
+            println!("{} set: {}", description, e);
+            false
+        },
+    }
+}
+
+//Returns whether every file is present, parses, holds the expected number of images and matches its checksum
+pub fn verify(paths: &DatasetPaths) -> bool {
+    let kind = paths.kind();
+    let sha256 = kind.sha256();
+    let decompressed_sha256 = kind.decompressed_sha256();
+    let (num_training_images, num_testing_images) = kind.num_images();
+    let results = [
+        verify_file("Training images", &paths.training_images(), sha256.map(|sha256| sha256[0]), decompressed_sha256[0]),
+        verify_file("Training labels", &paths.training_labels(), sha256.map(|sha256| sha256[1]), decompressed_sha256[1]),
+        verify_file("Test images", &paths.testing_images(), sha256.map(|sha256| sha256[2]), decompressed_sha256[2]),
+        verify_file("Test labels", &paths.testing_labels(), sha256.map(|sha256| sha256[3]), decompressed_sha256[3]),
+        verify_set("Training", paths.load_training(), num_training_images),
+        verify_set("Test", paths.load_testing(), num_testing_images),
+    ];
+    !results.contains(&false)
+}
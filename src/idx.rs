@@ -0,0 +1,391 @@
+//IDX file format, as used by MNIST: two zero bytes, a data type code, the number of dimensions, then one big endian
+//u32 size per dimension followed by the big endian elements in row major order
+
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{BufWriter, Cursor, Read, Write};
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use ndarray::{ArrayD, IxDyn};
+
+pub const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+#[derive(Debug)]
+pub enum IdxError {
+    Io(std::io::Error),
+    //The magic number doesn't start with two zero bytes, so this isn't an IDX file at all
+    BadMagicNumber(u32),
+    UnknownDataType(u8),
+    //Either the header or the payload ended before the header said it would
+    Truncated { expected_bytes: usize, found_bytes: usize },
+    //The header stores the number of dimensions in a byte and each dimension in a u32
+    TooManyDimensions(usize),
+    DimensionTooLarge(usize),
+    //More elements along the dimensions than can be indexed, which a zero dimension elsewhere keeps from looking truncated
+    BadShape(Vec<usize>),
+}
+
+impl Display for IdxError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IdxError::Io(e) => write!(f, "{}", e),
+            IdxError::BadMagicNumber(magic_number) => write!(f, "magic number {:#010x} is not an IDX magic number", magic_number),
+            IdxError::UnknownDataType(code) => write!(f, "unknown IDX data type {:#04x}", code),
+            IdxError::Truncated { expected_bytes, found_bytes } => write!(f, "truncated: expected {} bytes but found {}", expected_bytes, found_bytes),
+            IdxError::TooManyDimensions(dimensions) => write!(f, "{} dimensions is more than IDX can store, at most {}", dimensions, u8::MAX),
+            IdxError::DimensionTooLarge(dimension) => write!(f, "dimension of {} is more than IDX can store, at most {}", dimension, u32::MAX),
+            IdxError::BadShape(shape) => write!(f, "shape {:?} is too large to hold", shape),
+        }
+    }
+}
+
+impl std::error::Error for IdxError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            IdxError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for IdxError {
+    fn from(e: std::io::Error) -> Self {
+        IdxError::Io(e)
+    }
+}
+
+//An element type IDX can store
+pub trait IdxType: Copy + Default {
+    const TYPE_CODE: u8;
+    const SIZE: usize;
+
+    fn decode(bytes: &[u8], elements: &mut [Self]);
+    fn write<W: Write>(writer: &mut W, element: Self) -> std::io::Result<()>;
+}
+
+impl IdxType for u8 {
+    const TYPE_CODE: u8 = 0x08;
+    const SIZE: usize = 1;
+
+    fn decode(bytes: &[u8], elements: &mut [Self]) {
+        elements.copy_from_slice(bytes);
+    }
+
+    fn write<W: Write>(writer: &mut W, element: Self) -> std::io::Result<()> {
+        writer.write_u8(element)
+    }
+}
+
+impl IdxType for i8 {
+    const TYPE_CODE: u8 = 0x09;
+    const SIZE: usize = 1;
+
+    fn decode(bytes: &[u8], elements: &mut [Self]) {
+        for (element, &byte) in elements.iter_mut().zip(bytes) {
+            *element = byte as i8;
+        }
+    }
+
+    fn write<W: Write>(writer: &mut W, element: Self) -> std::io::Result<()> {
+        writer.write_i8(element)
+    }
+}
+
+impl IdxType for i16 {
+    const TYPE_CODE: u8 = 0x0B;
+    const SIZE: usize = 2;
+
+    fn decode(bytes: &[u8], elements: &mut [Self]) {
+        BigEndian::read_i16_into(bytes, elements);
+    }
+
+    fn write<W: Write>(writer: &mut W, element: Self) -> std::io::Result<()> {
+        writer.write_i16::<BigEndian>(element)
+    }
+}
+
+impl IdxType for i32 {
+    const TYPE_CODE: u8 = 0x0C;
+    const SIZE: usize = 4;
+
+    fn decode(bytes: &[u8], elements: &mut [Self]) {
+        BigEndian::read_i32_into(bytes, elements);
+    }
+
+    fn write<W: Write>(writer: &mut W, element: Self) -> std::io::Result<()> {
+        writer.write_i32::<BigEndian>(element)
+    }
+}
+
+impl IdxType for f32 {
+    const TYPE_CODE: u8 = 0x0D;
+    const SIZE: usize = 4;
+
+    fn decode(bytes: &[u8], elements: &mut [Self]) {
+        BigEndian::read_f32_into(bytes, elements);
+    }
+
+    fn write<W: Write>(writer: &mut W, element: Self) -> std::io::Result<()> {
+        writer.write_f32::<BigEndian>(element)
+    }
+}
+
+impl IdxType for f64 {
+    const TYPE_CODE: u8 = 0x0E;
+    const SIZE: usize = 8;
+
+    fn decode(bytes: &[u8], elements: &mut [Self]) {
+        BigEndian::read_f64_into(bytes, elements);
+    }
+
+    fn write<W: Write>(writer: &mut W, element: Self) -> std::io::Result<()> {
+        writer.write_f64::<BigEndian>(element)
+    }
+}
+
+//An IDX payload of whichever element type the file declared
+#[derive(Debug, PartialEq)]
+pub enum IdxArray {
+    U8(ArrayD<u8>),
+    I8(ArrayD<i8>),
+    I16(ArrayD<i16>),
+    I32(ArrayD<i32>),
+    F32(ArrayD<f32>),
+    F64(ArrayD<f64>),
+}
+
+impl IdxArray {
+    pub fn shape(&self) -> &[usize] {
+        match self {
+            IdxArray::U8(a) => a.shape(),
+            IdxArray::I8(a) => a.shape(),
+            IdxArray::I16(a) => a.shape(),
+            IdxArray::I32(a) => a.shape(),
+            IdxArray::F32(a) => a.shape(),
+            IdxArray::F64(a) => a.shape(),
+        }
+    }
+
+    //The magic number the array is written with, e.g. 2051 for a 3 dimensional u8 array of images
+    pub fn magic_number(&self) -> u32 {
+        let type_code = match self {
+            IdxArray::U8(_) => u8::TYPE_CODE,
+            IdxArray::I8(_) => i8::TYPE_CODE,
+            IdxArray::I16(_) => i16::TYPE_CODE,
+            IdxArray::I32(_) => i32::TYPE_CODE,
+            IdxArray::F32(_) => f32::TYPE_CODE,
+            IdxArray::F64(_) => f64::TYPE_CODE,
+        };
+        ((type_code as u32) << 8) | self.shape().len() as u32
+    }
+}
+
+fn decode_payload<T: IdxType>(shape: Vec<usize>, payload: &[u8]) -> Result<ArrayD<T>, IdxError> {
+    let mut elements = vec![T::default(); shape.iter().product()];
+    T::decode(&payload[..elements.len() * T::SIZE], &mut elements);
+    ArrayD::from_shape_vec(IxDyn(&shape), elements).map_err(|_| IdxError::BadShape(shape))
+}
+
+pub fn read_idx<R: Read>(mut reader: R) -> Result<IdxArray, IdxError> {
+    let mut contents: Vec<u8> = Vec::new();
+    reader.read_to_end(&mut contents)?;
+
+    if contents.len() < 4 {
+        return Err(IdxError::Truncated { expected_bytes: 4, found_bytes: contents.len() });
+    }
+    let magic_number = BigEndian::read_u32(&contents);
+    if magic_number >> 16 != 0 {
+        return Err(IdxError::BadMagicNumber(magic_number));
+    }
+    let type_code = contents[2];
+    let num_dimensions = contents[3] as usize;
+
+    let header_size = 4 + 4 * num_dimensions;
+    if contents.len() < header_size {
+        return Err(IdxError::Truncated { expected_bytes: header_size, found_bytes: contents.len() });
+    }
+    let shape: Vec<usize> = contents[4..header_size].chunks(4).map(|d| BigEndian::read_u32(d) as usize).collect();
+
+    let element_size = match type_code {
+        u8::TYPE_CODE | i8::TYPE_CODE => 1,
+        i16::TYPE_CODE => i16::SIZE,
+        i32::TYPE_CODE => i32::SIZE,
+        f32::TYPE_CODE => f32::SIZE,
+        f64::TYPE_CODE => f64::SIZE,
+        _ => return Err(IdxError::UnknownDataType(type_code)),
+    };
+    let expected_bytes = shape.iter().try_fold(element_size, |size, &d| size.checked_mul(d)).and_then(|size| size.checked_add(header_size)).unwrap_or(usize::MAX);
+    if contents.len() < expected_bytes {
+        return Err(IdxError::Truncated { expected_bytes, found_bytes: contents.len() });
+    }
+
+    let payload = &contents[header_size..];
+    Ok(match type_code {
+        u8::TYPE_CODE => IdxArray::U8(decode_payload(shape, payload)?),
+        i8::TYPE_CODE => IdxArray::I8(decode_payload(shape, payload)?),
+        i16::TYPE_CODE => IdxArray::I16(decode_payload(shape, payload)?),
+        i32::TYPE_CODE => IdxArray::I32(decode_payload(shape, payload)?),
+        f32::TYPE_CODE => IdxArray::F32(decode_payload(shape, payload)?),
+        _ => IdxArray::F64(decode_payload(shape, payload)?),
+    })
+}
+
+//Fails before writing anything if the shape doesn't fit in the header
+pub fn write_idx<T: IdxType, W: Write>(writer: &mut W, array: &ArrayD<T>) -> Result<(), IdxError> {
+    let num_dimensions = u8::try_from(array.ndim()).map_err(|_| IdxError::TooManyDimensions(array.ndim()))?;
+    let shape = array.shape().iter().map(|&dimension| u32::try_from(dimension).map_err(|_| IdxError::DimensionTooLarge(dimension))).collect::<Result<Vec<u32>, IdxError>>()?;
+
+    writer.write_u16::<BigEndian>(0)?;
+    writer.write_u8(T::TYPE_CODE)?;
+    writer.write_u8(num_dimensions)?;
+    for dimension in shape {
+        writer.write_u32::<BigEndian>(dimension)?;
+    }
+    //Iteration is in logical row major order whatever the memory layout
+    for &element in array.iter() {
+        T::write(writer, element)?;
+    }
+    Ok(())
+}
+
+pub fn write_idx_array<W: Write>(writer: &mut W, array: &IdxArray) -> Result<(), IdxError> {
+    match array {
+        IdxArray::U8(a) => write_idx(writer, a),
+        IdxArray::I8(a) => write_idx(writer, a),
+        IdxArray::I16(a) => write_idx(writer, a),
+        IdxArray::I32(a) => write_idx(writer, a),
+        IdxArray::F32(a) => write_idx(writer, a),
+        IdxArray::F64(a) => write_idx(writer, a),
+    }
+}
+
+//Reads IDX from any source, decompressing it first if it starts with the gzip magic bytes. MNIST is distributed
+//gzipped, but plenty of mirrors ship the files already decompressed
+pub fn read_idx_auto<R: Read>(mut reader: R) -> Result<IdxArray, IdxError> {
+    //A single read may return fewer bytes than asked for, from a pipe say, so keep reading until there are enough to tell
+    let mut prefix = Vec::with_capacity(GZIP_MAGIC.len());
+    (&mut reader).take(GZIP_MAGIC.len() as u64).read_to_end(&mut prefix)?;
+
+    let is_gzip = prefix == GZIP_MAGIC;
+    let reader = Cursor::new(prefix).chain(reader);
+    if is_gzip {
+        read_idx(GzDecoder::new(reader))
+    } else {
+        read_idx(reader)
+    }
+}
+
+//Gzips the file if its name ends in .gz
+pub fn write_idx_file(file_name: &str, array: &IdxArray) -> Result<(), IdxError> {
+    let writer = BufWriter::new(File::create(file_name)?);
+    if file_name.ends_with(".gz") {
+        let mut gz = GzEncoder::new(writer, Compression::default());
+        write_idx_array(&mut gz, array)?;
+        gz.finish()?.flush()?;
+    } else {
+        let mut writer = writer;
+        write_idx_array(&mut writer, array)?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use ndarray::{ArrayD, IxDyn};
+    use super::{read_idx, read_idx_auto, write_idx, write_idx_array, IdxArray, IdxError};
+
+    fn array<T>(shape: &[usize], element: impl Fn(usize) -> T) -> ArrayD<T> {
+        ArrayD::from_shape_vec(IxDyn(shape), (0..shape.iter().product()).map(element).collect()).unwrap()
+    }
+
+    //Every element type, at their extremes where they have them, in a variety of shapes
+    fn every_type() -> Vec<IdxArray> {
+        vec![
+            IdxArray::U8(array(&[3, 2, 2], |i| (i * 23) as u8)),
+            IdxArray::U8(array(&[4], |i| [0, 1, 254, u8::MAX][i])),
+            IdxArray::I8(array(&[2, 3], |i| [i8::MIN, -1, 0, 1, 100, i8::MAX][i])),
+            IdxArray::I16(array(&[5], |i| [i16::MIN, -300, 0, 300, i16::MAX][i])),
+            IdxArray::I32(array(&[2, 2], |i| [i32::MIN, -70000, 70000, i32::MAX][i])),
+            IdxArray::F32(array(&[1, 4], |i| [f32::MIN, -0.5, 1e-30, f32::MAX][i])),
+            IdxArray::F64(array(&[2, 1, 2], |i| [f64::MIN, -0.1, 1e-300, f64::MAX][i])),
+            IdxArray::F64(array(&[0, 3], |_| 0.0)),
+        ]
+    }
+
+    fn write(array: &IdxArray) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_idx_array(&mut bytes, array).unwrap();
+        bytes
+    }
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        gz.write_all(bytes).unwrap();
+        gz.finish().unwrap()
+    }
+
+    #[test]
+    fn every_type_round_trips() {
+        for array in every_type() {
+            let bytes = write(&array);
+            let (num_elements, element_size) = match &array {
+                IdxArray::U8(a) => (a.len(), 1),
+                IdxArray::I8(a) => (a.len(), 1),
+                IdxArray::I16(a) => (a.len(), 2),
+                IdxArray::I32(a) => (a.len(), 4),
+                IdxArray::F32(a) => (a.len(), 4),
+                IdxArray::F64(a) => (a.len(), 8),
+            };
+            assert_eq!(bytes.len(), 4 + 4 * array.shape().len() + num_elements * element_size);
+            assert_eq!(u32::from_be_bytes(bytes[..4].try_into().unwrap()), array.magic_number());
+
+            assert_eq!(read_idx(bytes.as_slice()).unwrap(), array);
+            assert_eq!(read_idx_auto(bytes.as_slice()).unwrap(), array);
+            assert_eq!(read_idx_auto(gzip(&bytes).as_slice()).unwrap(), array);
+        }
+    }
+
+    #[test]
+    fn truncated_payloads_are_rejected() {
+        for array in every_type().into_iter().filter(|array| array.shape().iter().product::<usize>() > 0) {
+            let bytes = write(&array);
+            let truncated = &bytes[..bytes.len() - 1];
+            for result in [read_idx(truncated), read_idx_auto(truncated), read_idx_auto(gzip(truncated).as_slice())] {
+                match result {
+                    Err(IdxError::Truncated { expected_bytes, found_bytes }) => assert_eq!((expected_bytes, found_bytes), (bytes.len(), truncated.len())),
+                    other => panic!("expected a truncated error for {:?}, got {:?}", array, other),
+                }
+            }
+        }
+    }
+
+    //No elements, so nothing is missing, but ndarray can't index 2^96 along the other dimensions
+    #[test]
+    fn oversized_empty_shapes_are_rejected() {
+        let mut bytes = vec![0, 0, 0x08, 4];
+        for dimension in [0, u32::MAX, u32::MAX, u32::MAX] {
+            bytes.extend_from_slice(&dimension.to_be_bytes());
+        }
+        for result in [read_idx(bytes.as_slice()), read_idx_auto(bytes.as_slice()), read_idx_auto(gzip(&bytes).as_slice())] {
+            assert!(matches!(result, Err(IdxError::BadShape(ref shape)) if shape.len() == 4), "{:?}", result);
+        }
+    }
+
+    #[test]
+    fn unrepresentable_shapes_are_rejected_before_writing() {
+        let mut bytes = Vec::new();
+        let too_many_dimensions = ArrayD::<u8>::zeros(IxDyn(&[1; 256]));
+        assert!(matches!(write_idx(&mut bytes, &too_many_dimensions), Err(IdxError::TooManyDimensions(256))));
+
+        let dimension = u32::MAX as usize + 1;
+        let too_large = ArrayD::<u8>::zeros(IxDyn(&[0, dimension]));
+        assert!(matches!(write_idx(&mut bytes, &too_large), Err(IdxError::DimensionTooLarge(d)) if d == dimension));
+        assert!(bytes.is_empty());
+    }
+}
@@ -1,24 +1,73 @@
 mod utils;
+mod idx;
 mod mnist;
+mod dataset;
+mod augmentation;
 mod networks;
 
 extern crate blas_src;
 
-use clap::{Parser, Subcommand, ValueEnum};
+use std::fmt::Display;
+use clap::builder::RangedU64ValueParser;
+use clap::{Parser, Subcommand};
+use ndarray::{Array2, Axis};
+use rand::rngs::StdRng;
+use rand::{thread_rng, Rng, SeedableRng};
+use idx::IdxArray;
+use augmentation::{AugmentationStep, Augmentations};
+use dataset::DatasetPaths;
+use networks::{Implementation, Network, SavedNetwork};
+use networks::activation::Activation;
+use networks::cost::CostKind;
+use networks::initializer::{BiasInitializer, InitializerKind};
+use networks::convolution::FeatureLayerKind;
+use networks::network::{BackpropMode, ConfigurableNetwork, NetworkConfig, IMAGE_SHAPE};
+use networks::normalization::NormalizationKind;
+use networks::optimizer::OptimizerKind;
+use networks::schedule::ScheduleKind;
+use networks::training::{Monitor, TrainingOptions};
+use networks::network1::Network1;
+use networks::network2::Network2;
 
 #[derive(Parser)]
 #[command()]
 struct Args {
     #[command(subcommand)]
-    command: Option<Commands>
+    command: Option<Commands>,
+
+    #[command(flatten)]
+    dataset: DatasetPaths,
+
+    #[arg(long, global = true, help = "Seed for every random choice: weight initialization, shuffling and augmentation. The same seed and options give identical weights on the same machine. Random if not given")]
+    seed: Option<u64>,
 }
 
+//Parsed once, so Train carrying every training option as a large variant costs nothing
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand)]
 enum Commands {
     Train {
         #[arg(short, long, default_value = "network2")]
         implementation: Implementation,
 
+        #[arg(long, value_delimiter = ',', help = "Comma separated neurons per fully connected layer, from the 784 input neurons (or the size of the --features output) through any number of hidden layers to one output neuron per class of the dataset, e.g. 784,100,30,10 for MNIST")]
+        layers: Option<Vec<usize>>,
+
+        #[arg(long, value_delimiter = ',', help = "Comma separated convolutional and pooling layers ahead of the fully connected ones: conv:maps:kernel[:stride[:padding]][:activation], maxpool:size or avgpool:size, e.g. conv:20:5,maxpool:2,conv:40:5,maxpool:2 with --layers 640,100,10")]
+        features: Vec<FeatureLayerKind>,
+
+        #[arg(long, value_delimiter = ',', help = "Comma separated activation function per layer after the input layer, e.g. relu,relu,softmax. A softmax output layer is trained with the log-likelihood cost unless --cost says otherwise")]
+        activations: Option<Vec<Activation>>,
+
+        #[arg(long, help = "Cost function to train against, overriding the implementation's. Cross-entropy, log-likelihood and focal need a sigmoid or softmax output layer, hinge suits an identity one")]
+        cost: Option<CostKind>,
+
+        #[arg(long, help = "Weight initialization, overriding the implementation's: large (standard normal, network1's), lecun (standard normal over sqrt(fan in), network2's), xavier-uniform or xavier-normal for sigmoid and tanh layers, he for relu layers, or orthogonal")]
+        initializer: Option<InitializerKind>,
+
+        #[arg(long, default_value = "normal", help = "Bias initialization: normal (standard normal), zeros or constant:value, e.g. constant:0.1")]
+        bias_initializer: BiasInitializer,
+
         #[arg(short, long, help = "Number of training cycles. One epoch cycles the entire dataset once.")]
         epochs: Option<usize>,
 
@@ -28,74 +77,392 @@ enum Commands {
         #[arg(short, long, help = "Controls the rate of gradient descent. A learning rate too high may overshoot the minimum point, whilst too low may perform poorly")]
         learning_rate: Option<f64>,
 
+        #[arg(long, default_value = "constant", help = "How the learning rate changes from epoch to epoch. Plateau decays it whenever validation accuracy stops improving, ending training at 1/128th of the initial rate")]
+        schedule: ScheduleKind,
+
+        #[arg(long, value_parser = parse_decay, help = "Learning rate multiplier of the step, exponential and plateau schedules, greater than 0 and less than 1. Defaults to 0.5, or 0.95 for exponential")]
+        decay: Option<f64>,
+
+        #[arg(long, default_value_t = 10, value_parser = RangedU64ValueParser::<usize>::new().range(1..), help = "Epochs between each decay of the step schedule")]
+        step_epochs: usize,
+
+        #[arg(long, default_value_t = 10, help = "Epochs without validation improvement before the plateau schedule decays the learning rate")]
+        patience: usize,
+
+        #[arg(long, default_value_t = 0, help = "Epochs to linearly ramp the learning rate up over before following the schedule")]
+        warmup_epochs: usize,
+
         #[arg(short, long, help = "Controls the rate of regularization to prevent over fitting to the training data, resulting in poor generalisation")]
         lambda: Option<f64>,
 
+        #[arg(long, default_value_t = 1.0, help = "Chance each hidden neuron is kept for each training image, with the rest dropped out. 1 disables dropout, 0.5 is typical")]
+        keep_probability: f64,
+
+        #[arg(long, default_value_t = 1.0, help = "Chance each input pixel is kept for each training image. 1 disables input dropout, 0.8 is typical")]
+        input_keep_probability: f64,
+
+        #[arg(long, help = "Batch normalize every hidden layer's weighted inputs before its activation. Needs --backprop matrix, as a lone image has no batch statistics")]
+        batch_norm: bool,
+
+        #[arg(long, default_value = "sgd", help = "How the averaged gradient of each batch is stepped into the weights and biases")]
+        optimizer: OptimizerKind,
+
+        #[arg(long, default_value_t = 0.9, help = "Velocity coefficient of the momentum and nesterov optimizers")]
+        momentum: f64,
+
+        #[arg(long, default_value = "per-image", help = "Backpropagate one image at a time, or the whole batch at once as a matrix")]
+        backprop: BackpropMode,
+
+        #[arg(long, default_value = "unit", help = "How pixels are transformed before the input layer: kept in [0, 1], mapped to [-1, 1], standardized per pixel or globally, or PCA whitened. Statistics are fitted to the training images and saved with the network")]
+        normalization: NormalizationKind,
+
+        #[arg(long, value_delimiter = ',', help = "Comma separated augmentations applied to each training image as it's batched, each name[:parameter[:parameter]][@probability]: shift:pixels, rotate:degrees, scale:max_change, elastic:alpha:sigma, noise:std_dev or erase:max_area, e.g. shift:2,rotate:10@0.5")]
+        augment: Vec<AugmentationStep>,
+
+        #[arg(short, long, default_value_t = 10000, help = "How many of the training images to hold out for per epoch validation. 0 validates against the test images instead")]
+        validation_size: usize,
+
+        #[arg(long, help = "Stop training once validation accuracy hasn't improved in this many epochs, and restore the best weights")]
+        early_stopping: Option<usize>,
+
+        #[arg(long, help = "Compute the cost on the training images after every epoch")]
+        monitor_training_cost: bool,
+
+        #[arg(long, help = "Compute the accuracy on the training images after every epoch, to compare against validation accuracy for over fitting")]
+        monitor_training_accuracy: bool,
+
+        #[arg(long, help = "Compute the cost on the validation images after every epoch")]
+        monitor_evaluation_cost: bool,
+
         #[arg(short, long, help = "Specify a file_name to save network results into")]
         save_file: Option<String>
     },
     Load {
-        #[arg(required = true)]
+        #[arg(required = true, help = "A file previously written by train --save-file")]
+        file_name: String,
+    },
+    #[command(about = "Check a saved network's backpropagated gradients against finite differences on the first few test images, printing the relative error of every layer that learns. Train with -e 0 to check an untrained network")]
+    CheckGradients {
+        #[arg(required = true, help = "A file previously written by train --save-file")]
         file_name: String,
+
+        #[arg(short = 'n', long, default_value_t = 4, help = "How many test images to sum the cost over. Every parameter takes two passes over them")]
+        images: usize,
+
+        #[arg(long, default_value_t = 1e-5, help = "How far each parameter is nudged either way")]
+        epsilon: f64,
+    },
+    Dataset {
+        #[command(subcommand)]
+        command: DatasetCommands,
+    },
+    Evaluate {
+        #[arg(required = true, help = "A file previously written by train --save-file")]
+        file_name: String,
+
+        #[arg(short, long, default_value_t = 3, help = "Also count an image as correct if its label is among this many most certain outputs")]
+        top_k: usize,
+
+        #[arg(short, long, help = "Print the test image indices of every misclassified image")]
+        misclassified: bool,
+
+        #[arg(short, long, help = "Write the output layer activations for every test image to this IDX file, as f64 with one row per image. Gzipped if it ends in .gz")]
+        predictions_file: Option<String>,
+    }
+}
+
+#[derive(Subcommand)]
+enum DatasetCommands {
+    #[command(about = "Check all four dataset files exist, parse, hold the expected number of images and match their known checksums")]
+    Verify,
+    #[command(about = "Write the training set followed by augmented copies of it as a new pair of IDX files, to train on with --training-images and --training-labels")]
+    Expand {
+        #[arg(long, value_delimiter = ',', required = true, help = "Comma separated augmentations to make each copy with, as for train --augment, e.g. shift:1 to move each copied image by a random -1, 0 or 1 pixels along each axis. Unlike Nielsen's expand_mnist, which shifts every image exactly one pixel up, down, left and right, copies can be diagonal or unshifted")]
+        augment: Vec<AugmentationStep>,
+
+        #[arg(short, long, default_value_t = 4, help = "How many augmented copies of the training set to append")]
+        copies: usize,
+
+        #[arg(long, required = true, help = "IDX file to write the expanded images to. Gzipped if it ends in .gz")]
+        images_file: String,
+
+        #[arg(long, required = true, help = "IDX file to write the expanded labels to. Gzipped if it ends in .gz")]
+        labels_file: String,
+    },
+}
+
+//A decay factor has to shrink the learning rate without reversing it
+fn parse_decay(s: &str) -> Result<f64, String> {
+    let decay: f64 = s.parse().map_err(|e: std::num::ParseFloatError| e.to_string())?;
+    if decay > 0.0 && decay < 1.0 {
+        Ok(decay)
+    } else {
+        Err(format!("{} is not greater than 0 and less than 1", decay))
     }
 }
 
+fn exit_with_error(error: impl Display) -> ! {
+    eprintln!("error: {}", error);
+    std::process::exit(1)
+}
 
-#[derive(ValueEnum, Clone, Debug)]
-enum Implementation {
-    Network1,
-    Network2,
+//The dataset a saved network is evaluated on defaults to the one it was trained on, and must have as many classes as it has outputs
+fn dataset_for(paths: &DatasetPaths, config: &NetworkConfig) -> DatasetPaths {
+    let mut paths = paths.clone();
+    let kind = *paths.kind.get_or_insert(config.dataset);
+    let num_outputs = config.structure.last().copied().unwrap_or(0);
+    if kind.num_classes() != num_outputs {
+        exit_with_error(format!("--dataset {} has {} classes but the network has {} output neurons", kind, kind.num_classes(), num_outputs));
+    }
+    paths
 }
 
 fn main() {
     let args = Args::parse();
+    let seed = args.seed.unwrap_or_else(|| thread_rng().gen());
+    let mut rng = StdRng::seed_from_u64(seed);
 
 
     match args.command.unwrap_or(Commands::Train {
         implementation: Implementation::Network2,
+        layers: None,
+        features: Vec::new(),
+        activations: None,
+        cost: None,
+        initializer: None,
+        bias_initializer: BiasInitializer::Normal,
         epochs: None,
         batch_size: None,
         learning_rate: None,
+        schedule: ScheduleKind::Constant,
+        decay: None,
+        step_epochs: 10,
+        patience: 10,
+        warmup_epochs: 0,
         lambda: None,
+        keep_probability: 1.0,
+        input_keep_probability: 1.0,
+        batch_norm: false,
+        optimizer: OptimizerKind::Sgd,
+        momentum: 0.9,
+        backprop: BackpropMode::PerImage,
+        normalization: NormalizationKind::Unit,
+        augment: Vec::new(),
+        validation_size: 10000,
+        early_stopping: None,
+        monitor_training_cost: false,
+        monitor_training_accuracy: false,
+        monitor_evaluation_cost: false,
         save_file: None,
     } ) {
         Commands::Train {
             implementation,
+            layers,
+            features,
+            activations,
+            cost,
+            initializer,
+            bias_initializer,
             epochs,
             batch_size,
             learning_rate,
+            schedule,
+            decay,
+            step_epochs,
+            patience,
+            warmup_epochs,
             lambda,
+            keep_probability,
+            input_keep_probability,
+            batch_norm,
+            optimizer,
+            momentum,
+            backprop,
+            normalization,
+            augment,
+            validation_size,
+            early_stopping,
+            monitor_training_cost,
+            monitor_training_accuracy,
+            monitor_evaluation_cost,
             save_file
         } => {
-            let mut training_data = mnist::load_mnist_file("train-images-idx3-ubyte.gz", "train-labels-idx1-ubyte.gz").unwrap();
-            let testing_data = mnist::load_mnist_file("t10k-images-idx3-ubyte.gz", "t10k-labels-idx1-ubyte.gz").unwrap();
+            let kind = args.dataset.kind();
+            let num_classes = kind.num_classes();
+            let feature_shape = networks::convolution::output_shape(&features, IMAGE_SHAPE).unwrap_or_else(|e| exit_with_error(format!("invalid --features: {}", e)));
+            let num_inputs = feature_shape.size();
+            let structure = layers.unwrap_or(vec![num_inputs, 30, num_classes]);
+            if structure.len() < 2 || structure[0] != num_inputs || structure[structure.len() - 1] != num_classes {
+                exit_with_error(format!("--layers must start with {} input neurons and end with {} output neurons for {}, got {:?}", num_inputs, num_classes, kind, structure));
+            }
 
-            match implementation {
-                Implementation::Network1 => {
-                    let mut network = networks::network1::Network1::new(&[784, 30, 10]);
+            for (flag, probability) in [("--keep-probability", keep_probability), ("--input-keep-probability", input_keep_probability)] {
+                if !(probability > 0.0 && probability <= 1.0) {
+                    exit_with_error(format!("{} must be greater than 0 and at most 1, got {}", flag, probability));
+                }
+            }
 
-                    let epochs = epochs.unwrap_or(30);
-                    let batch_size = batch_size.unwrap_or(10);
-                    let learning_rate = learning_rate.unwrap_or(3.0);
+            if batch_norm && matches!(backprop, BackpropMode::PerImage) {
+                exit_with_error("--batch-norm needs --backprop matrix");
+            }
 
-                    network.train(&mut training_data, testing_data.as_slice(), epochs, batch_size, learning_rate);
-                },
-                Implementation::Network2 => {
-                    let mut network = networks::network2::Network2::new(&[784, 30, 10]);
+            if validation_size == 0 && early_stopping.is_some() {
+                exit_with_error("--early-stopping needs a validation set, it would otherwise be tuned against the test images");
+            }
 
-                    let epochs = epochs.unwrap_or(30);
-                    let batch_size = batch_size.unwrap_or(10);
-                    let learning_rate = learning_rate.unwrap_or(0.1);
-                    let lambda = lambda.unwrap_or(5.0);
+            let training_data = args.dataset.load_training().unwrap_or_else(|e| exit_with_error(e));
+            let testing_data = args.dataset.load_testing().unwrap_or_else(|e| exit_with_error(e));
+            if validation_size >= training_data.len() {
+                exit_with_error(format!("--validation-size {} leaves none of the {} training images to train on", validation_size, training_data.len()));
+            }
+            let (training_data, validation_data) = mnist::split_validation(training_data, validation_size);
 
-                    network.train(&mut training_data, testing_data.as_slice(), epochs, batch_size, learning_rate, lambda);
+            let epochs = epochs.unwrap_or(30);
+            let mut options = TrainingOptions {
+                epochs,
+                batch_size: batch_size.unwrap_or(10),
+                early_stopping,
+                monitor: Monitor {
+                    training_cost: monitor_training_cost,
+                    training_accuracy: monitor_training_accuracy,
+                    evaluation_cost: monitor_evaluation_cost,
                 },
+                augmentations: Augmentations::new(&augment),
+            };
+
+            let (mut config, learning_rate) = match implementation {
+                Implementation::Network1 => (Network1::config(&structure, backprop), learning_rate.unwrap_or(3.0)),
+                Implementation::Network2 => (Network2::config(&structure, lambda.unwrap_or(5.0), backprop), learning_rate.unwrap_or(0.1)),
+            };
+
+            if let Some(activations) = activations {
+                if activations.len() != structure.len() - 1 {
+                    exit_with_error(format!("--activations needs one activation per layer after the input layer, {} for {:?}", structure.len() - 1, structure));
+                }
+                config.activations = activations;
+            }
+            if let Some(initializer) = initializer {
+                config.initializer = initializer;
+            }
+            config.bias_initializer = bias_initializer;
+            config.optimizer = optimizer;
+            config.momentum = momentum;
+            config.dataset = kind;
+            config.input_keep_probability = input_keep_probability;
+            config.hidden_keep_probability = keep_probability;
+            config.features = features;
+            config.batch_norm = batch_norm;
+            let output_activation = *config.activations.last().unwrap();
+            config.cost = match cost {
+                Some(cost) => cost,
+                None if output_activation == Activation::Softmax => CostKind::LogLikelihood,
+                None => config.cost,
+            };
+            if config.cost.needs_probabilities() && !matches!(output_activation, Activation::Sigmoid | Activation::Softmax) {
+                exit_with_error(format!("the {} cost needs a sigmoid or softmax output layer, not {}. Choose another with --cost", config.cost, output_activation));
+            }
+
+            println!("Seed: {}", seed);
+            let mut network = ConfigurableNetwork::new(config, &mut rng);
+            network.set_normalization(normalization.fit(&training_data).unwrap_or_else(|e| exit_with_error(format!("could not fit --normalization: {}", e))));
+            let mut schedule = schedule.build(learning_rate, decay, step_epochs, patience, warmup_epochs, epochs);
+
+            let history = if validation_data.is_empty() {
+                options.early_stopping = None;
+                network.train(&training_data, &testing_data, schedule.as_mut(), &options, &mut rng)
+            } else {
+                network.train(&training_data, &validation_data, schedule.as_mut(), &options, &mut rng)
             };
+
+            if let Some(best) = history.best_epoch() {
+                println!("Best evaluation accuracy: {}% in epoch {}", best.evaluation_accuracy, best.epoch);
+            }
+            if !validation_data.is_empty() {
+                println!("Test accuracy: {}%", network.evaluate(&testing_data));
+            }
+
+            if let Some(save_file) = save_file {
+                network.to_saved().save(&save_file).unwrap_or_else(|e| exit_with_error(format!("could not save {}: {}", save_file, e)));
+                println!("Saved network to {}", save_file);
+            }
         },
         Commands::Load {
             file_name
         } => {
+            let saved = SavedNetwork::load(&file_name).unwrap_or_else(|e| exit_with_error(format!("could not load {}: {}", file_name, e)));
+            let testing_data = dataset_for(&args.dataset, &saved.config).load_testing().unwrap_or_else(|e| exit_with_error(e));
+
+            println!("{}", saved.config);
+            println!("Normalization: {}", saved.normalization.kind());
+
+            let mut network = ConfigurableNetwork::from_saved(saved);
+
+            println!("Test accuracy: {}%", network.evaluate(&testing_data));
+        }
+        Commands::CheckGradients {
+            file_name,
+            images,
+            epsilon
+        } => {
+            let saved = SavedNetwork::load(&file_name).unwrap_or_else(|e| exit_with_error(format!("could not load {}: {}", file_name, e)));
+            let testing_data = dataset_for(&args.dataset, &saved.config).load_testing().unwrap_or_else(|e| exit_with_error(e));
+            let images = testing_data.select(&(0..images.min(testing_data.len())).collect::<Vec<usize>>());
+
+            let mut network = ConfigurableNetwork::from_saved(saved);
+            let checks = network.check_gradients(&images, epsilon);
+            for check in &checks {
+                println!("{}", check);
+            }
+            println!("Largest relative error: {:.2e}", checks.iter().map(|check| check.max_error()).fold(0.0, f64::max));
+        },
+        Commands::Dataset {
+            command: DatasetCommands::Verify
+        } => {
+            if !dataset::verify(&args.dataset) {
+                exit_with_error("dataset verification failed");
+            }
+        },
+        Commands::Dataset {
+            command: DatasetCommands::Expand { augment, copies, images_file, labels_file }
+        } => {
+            let training_data = args.dataset.load_training().unwrap_or_else(|e| exit_with_error(e));
+            let expanded = augmentation::expand(&training_data, &Augmentations::new(&augment), copies, &mut rng);
+
+            mnist::write_mnist_files(&expanded, &images_file, &labels_file, args.dataset.kind().format()).unwrap_or_else(|e| exit_with_error(format!("could not write {} and {}: {}", images_file, labels_file, e)));
+            println!("Saved {} images to {} and {}", expanded.len(), images_file, labels_file);
+        },
+        Commands::Evaluate {
+            file_name,
+            top_k,
+            misclassified,
+            predictions_file
+        } => {
+            let saved = SavedNetwork::load(&file_name).unwrap_or_else(|e| exit_with_error(format!("could not load {}: {}", file_name, e)));
+            let dataset = dataset_for(&args.dataset, &saved.config);
+            let testing_data = dataset.load_testing().unwrap_or_else(|e| exit_with_error(e));
+
+            let mut network = ConfigurableNetwork::from_saved(saved);
+            let mut report = network.evaluate_report(&testing_data, top_k);
+            report.class_names = dataset.kind().class_names();
+
+            print!("{}", report);
+            if misclassified {
+                println!();
+                println!("Misclassified images: {:?}", report.misclassified);
+            }
+
+            if let Some(predictions_file) = predictions_file {
+                let mut input = Array2::zeros((testing_data.image_size(), 1));
+                let outputs: Vec<Array2<f64>> = testing_data.views().map(|image| {
+                    image.image_into(&mut input);
+                    network.feed_forward(&input).t().to_owned()
+                }).collect();
+                let views: Vec<_> = outputs.iter().map(|output| output.view()).collect();
+                let predictions = ndarray::concatenate(Axis(0), &views).unwrap();
 
+                idx::write_idx_file(&predictions_file, &IdxArray::F64(predictions.into_dyn())).unwrap_or_else(|e| exit_with_error(format!("could not write {}: {}", predictions_file, e)));
+                println!("Saved predictions to {}", predictions_file);
+            }
         }
     }
 }
\ No newline at end of file
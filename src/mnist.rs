@@ -1,48 +1,84 @@
+use std::fmt::{Display, Formatter};
 use std::fs::File;
-use std::io::{Cursor, Read};
-use byteorder::{BigEndian, ReadBytesExt};
-use flate2::read::GzDecoder;
-use ndarray::{Array1, Array2};
-
-struct MnistData {
-    metadata: Vec<i32>,
-    data: Vec<u8>
-}
-
-impl MnistData {
-    pub fn new(f: &File) -> Result<MnistData, std::io::Error> {
-        let mut gz = GzDecoder::new(f);
-        let mut contents: Vec<u8> = Vec::new();
-        gz.read_to_end(&mut contents)?;
-
-        let mut cursor = Cursor::new(&contents);
-
-        let mut metadata: Vec<i32> = Vec::new();
-        let mut data: Vec<u8> = Vec::new();
-
-        let magic_number = cursor.read_i32::<BigEndian>()?;
-        match magic_number {
-            2049 => { // set labels
-                let num_items = cursor.read_i32::<BigEndian>()?;
-                metadata.push(num_items);
-            },
-            2051 => { //set images
-                let num_images = cursor.read_i32::<BigEndian>()?;
-                let num_rows = cursor.read_i32::<BigEndian>()?;
-                let num_columns = cursor.read_i32::<BigEndian>()?;
-                metadata.push(num_images);
-                metadata.push(num_rows);
-                metadata.push(num_columns);
-            },
-            _ => panic!()
+use std::io::{BufReader, Read};
+use ndarray::{s, Array1, Array2, ArrayD, ArrayView1, ArrayView2, Zip};
+use crate::idx::{read_idx_auto, write_idx_file, IdxArray, IdxError};
+
+//u8 IDX files of 1 and 3 dimensions respectively
+const LABEL_MAGIC_NUMBER: u32 = 2049;
+const IMAGE_MAGIC_NUMBER: u32 = 2051;
+
+pub const IMAGE_ROWS: usize = 28;
+pub const IMAGE_COLUMNS: usize = 28;
+
+//How a dataset in the MNIST file layout differs from MNIST itself
+#[derive(Clone, Copy, Debug)]
+pub struct MnistFormat {
+    pub num_classes: usize,
+    //The label of the first class in the file, e.g. EMNIST letters numbers A to Z from 1
+    pub first_label: u8,
+    //Images are stored column major, as in EMNIST, and are transposed back on load
+    pub transposed: bool,
+}
+
+impl MnistFormat {
+    pub const MNIST: MnistFormat = MnistFormat { num_classes: 10, first_label: 0, transposed: false };
+}
+
+#[derive(Debug)]
+pub enum MnistError {
+    Io { file_name: String, source: std::io::Error },
+    //A valid IDX file, but not of the type and dimensionality expected
+    BadMagicNumber { file_name: String, expected: u32, found: u32 },
+    //Either the header or the payload ended before the header said it would
+    Truncated { file_name: String, expected_bytes: usize, found_bytes: usize },
+    CountMismatch { num_images: usize, num_labels: usize },
+    UnexpectedDimensions { file_name: String, rows: usize, columns: usize },
+    LabelOutOfRange { file_name: String, index: usize, label: u8, first_label: u8, num_classes: usize },
+    //Anything else that makes the file not valid IDX
+    Idx { file_name: String, source: IdxError },
+}
+
+impl Display for MnistError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MnistError::Io { file_name, source } => write!(f, "could not read {}: {}", file_name, source),
+            MnistError::BadMagicNumber { file_name, expected, found } => write!(f, "{} has magic number {}, expected {} ({} is labels, {} is images)", file_name, found, expected, LABEL_MAGIC_NUMBER, IMAGE_MAGIC_NUMBER),
+            MnistError::Truncated { file_name, expected_bytes, found_bytes } => write!(f, "{} is truncated: expected {} bytes but found {}", file_name, expected_bytes, found_bytes),
+            MnistError::CountMismatch { num_images, num_labels } => write!(f, "image file has {} images but label file has {} labels", num_images, num_labels),
+            MnistError::UnexpectedDimensions { file_name, rows, columns } => write!(f, "{} has {}x{} images, expected {}x{}", file_name, rows, columns, IMAGE_ROWS, IMAGE_COLUMNS),
+            MnistError::LabelOutOfRange { file_name, index, label, first_label, num_classes } => write!(f, "{} has label {} at index {}, expected {} to {}", file_name, label, index, first_label, *first_label as usize + num_classes - 1),
+            MnistError::Idx { file_name, source } => write!(f, "{} is not a valid IDX file: {}", file_name, source),
         }
+    }
+}
 
-        cursor.read_to_end(&mut data)?;
+impl std::error::Error for MnistError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MnistError::Io { source, .. } => Some(source),
+            MnistError::Idx { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
 
-        Ok(MnistData { metadata, data })
+//Reads a u8 IDX file, gzipped or not, checking it holds the kind of data the magic number identifies.
+//file_name only names the source in errors
+fn read_mnist_idx<R: Read>(reader: R, file_name: &str, expected_magic_number: u32) -> Result<ArrayD<u8>, MnistError> {
+    let array = read_idx_auto(reader).map_err(|e| match e {
+        IdxError::Io(source) => MnistError::Io { file_name: file_name.to_string(), source },
+        IdxError::Truncated { expected_bytes, found_bytes } => MnistError::Truncated { file_name: file_name.to_string(), expected_bytes, found_bytes },
+        source => MnistError::Idx { file_name: file_name.to_string(), source },
+    })?;
+
+    match array {
+        IdxArray::U8(array) if array.ndim() as u32 == expected_magic_number & 0xFF => Ok(array),
+        array => Err(MnistError::BadMagicNumber { file_name: file_name.to_string(), expected: expected_magic_number, found: array.magic_number() }),
     }
 }
 
+//A single image converted to floats, as the networks consume it
 #[derive(Debug)]
 pub struct MnistImage {
     pub image: Array2<f64>,
@@ -50,38 +86,168 @@ pub struct MnistImage {
     pub label: u8,
 }
 
-pub fn load_mnist_file(image_file_name: &str, label_file_name: &str) -> Result<Vec<MnistImage>, std::io::Error> {
-    let image_data = MnistData::new(&(File::open(image_file_name)?))?;
-    let label_data = MnistData::new(&(File::open(label_file_name)?))?;
+//A single image borrowed from its dataset, for inference. Its floats are written into buffers reused from image to
+//image, rather than allocated afresh as an MnistImage is
+#[derive(Clone, Copy, Debug)]
+pub struct MnistImageView<'a> {
+    pub pixels: ArrayView1<'a, u8>,
+    pub label: u8,
+    pub num_classes: usize,
+}
 
-    let num_images = image_data.metadata[0];
-    let num_labels = label_data.metadata[0];
-    let num_pixel_rows = image_data.metadata[1];
-    let num_pixel_columns = image_data.metadata[2];
-    let image_size = (num_pixel_rows * num_pixel_columns) as usize;
+impl MnistImageView<'_> {
+    //Into a column of as many floats as there are pixels
+    pub fn image_into(&self, image: &mut Array2<f64>) {
+        Zip::from(image.column_mut(0)).and(&self.pixels).for_each(|x, &pixel| *x = pixel as f64 / 255.);
+    }
 
-    assert_eq!(num_images, num_labels);
+    //Into a one hot column of num_classes floats
+    pub fn label_array_into(&self, label_array: &mut Array2<f64>) {
+        label_array.fill(0.0);
+        label_array[(self.label as usize, 0)] = 1.0;
+    }
 
-    let mut images: Vec<MnistImage> = Vec::with_capacity(num_images as usize);
+    pub fn to_image(self) -> MnistImage {
+        let mut image = Array2::zeros((self.pixels.len(), 1));
+        self.image_into(&mut image);
+        let mut label_array = Array2::zeros((self.num_classes, 1));
+        self.label_array_into(&mut label_array);
+        MnistImage { image, label_array, label: self.label }
+    }
+}
 
-    for i in 0..num_images as usize {
-        let start_offset = i * image_size;
-        let end_offset = (i+1) * image_size;
-        let image_data = image_data.data[start_offset..end_offset].iter().map(|&x|  x as f64 / 255.);
+//A file name of - reads from stdin
+fn open_mnist_file(file_name: &str) -> Result<Box<dyn Read>, MnistError> {
+    if file_name == "-" {
+        return Ok(Box::new(std::io::stdin().lock()));
+    }
+    match File::open(file_name) {
+        Ok(f) => Ok(Box::new(BufReader::new(f))),
+        Err(source) => Err(MnistError::Io { file_name: file_name.to_string(), source }),
+    }
+}
 
-        let mut label = Array2::zeros((10,1));
-        label[(label_data.data[i] as usize, 0)] = 1.0;
+pub fn load_mnist_file(image_file_name: &str, label_file_name: &str, format: MnistFormat) -> Result<MnistDataset, MnistError> {
+    load_mnist(open_mnist_file(image_file_name)?, image_file_name, open_mnist_file(label_file_name)?, label_file_name, format)
+}
 
+//Loads from any pair of readers, such as in memory buffers. The names only identify the sources in errors.
+//Labels are renumbered from 0 and images are returned in row major order whatever the format stores them as
+pub fn load_mnist<I: Read, L: Read>(image_reader: I, image_file_name: &str, label_reader: L, label_file_name: &str, format: MnistFormat) -> Result<MnistDataset, MnistError> {
+    let image_data = read_mnist_idx(image_reader, image_file_name, IMAGE_MAGIC_NUMBER)?;
+    let label_data = read_mnist_idx(label_reader, label_file_name, LABEL_MAGIC_NUMBER)?;
 
-        let a: Array1<f64> = Array1::from_iter(image_data);
-        let b: Array2<f64> = a.into_shape((784,1)).unwrap();
+    let num_images = image_data.shape()[0];
+    let num_labels = label_data.shape()[0];
+    let num_pixel_rows = image_data.shape()[1];
+    let num_pixel_columns = image_data.shape()[2];
 
-        images.push(MnistImage {
-            image: b,
-            label_array: label,
-            label: label_data.data[i]
-        });
+    if num_pixel_rows != IMAGE_ROWS || num_pixel_columns != IMAGE_COLUMNS {
+        return Err(MnistError::UnexpectedDimensions { file_name: image_file_name.to_string(), rows: num_pixel_rows, columns: num_pixel_columns });
     }
+    if num_images != num_labels {
+        return Err(MnistError::CountMismatch { num_images, num_labels });
+    }
+
+    let mut labels = Vec::with_capacity(num_labels);
+    for (i, &label) in label_data.iter().enumerate() {
+        if label < format.first_label || (label - format.first_label) as usize >= format.num_classes {
+            return Err(MnistError::LabelOutOfRange { file_name: label_file_name.to_string(), index: i, label, first_label: format.first_label, num_classes: format.num_classes });
+        }
+        labels.push(label - format.first_label);
+    }
+
+    //Freshly read arrays are in standard layout, so only transposed images need copying before flattening
+    let image_data = if format.transposed {
+        image_data.permuted_axes(vec![0, 2, 1]).as_standard_layout().into_owned()
+    } else {
+        image_data
+    };
+    let pixels = image_data.into_shape((num_images, IMAGE_ROWS * IMAGE_COLUMNS)).unwrap();
+
+    Ok(MnistDataset::new(pixels, labels, format.num_classes))
+}
+
+//A whole dataset as one contiguous u8 matrix with a row of pixels per image, 784 bytes an image rather than the 6.3 KB
+//of an MnistImage. Images are converted to MnistImage floats only as they're used, a batch at a time, or viewed in place
+#[derive(Clone, Debug)]
+pub struct MnistDataset {
+    pixels: Array2<u8>,
+    labels: Vec<u8>,
+    num_classes: usize,
+}
+
+impl MnistDataset {
+    pub fn new(pixels: Array2<u8>, labels: Vec<u8>, num_classes: usize) -> MnistDataset {
+        assert_eq!(pixels.nrows(), labels.len(), "one label per image");
+        MnistDataset { pixels, labels, num_classes }
+    }
+
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    pub fn num_classes(&self) -> usize {
+        self.num_classes
+    }
+
+    pub fn pixels(&self) -> ArrayView2<'_, u8> {
+        self.pixels.view()
+    }
+
+    pub fn labels(&self) -> &[u8] {
+        &self.labels
+    }
+
+    //Pixels per image
+    pub fn image_size(&self) -> usize {
+        self.pixels.ncols()
+    }
+
+    pub fn view(&self, index: usize) -> MnistImageView<'_> {
+        MnistImageView { pixels: self.pixels.row(index), label: self.labels[index], num_classes: self.num_classes }
+    }
+
+    pub fn views(&self) -> impl Iterator<Item = MnistImageView<'_>> + '_ {
+        (0..self.len()).map(|index| self.view(index))
+    }
+
+    pub fn get(&self, index: usize) -> MnistImage {
+        self.view(index).to_image()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = MnistImage> + '_ {
+        (0..self.len()).map(|index| self.get(index))
+    }
+
+    //The images at the given indices, e.g. a shuffled batch
+    pub fn select(&self, indices: &[usize]) -> Vec<MnistImage> {
+        indices.iter().map(|&index| self.get(index)).collect()
+    }
+
+    fn slice(&self, start: usize, end: usize) -> MnistDataset {
+        MnistDataset::new(self.pixels.slice(s![start..end, ..]).to_owned(), self.labels[start..end].to_vec(), self.num_classes)
+    }
+}
+
+//The inverse of load_mnist, writing the images and labels back in the layout and numbering of the format.
+//Each file is gzipped if its name ends in .gz
+pub fn write_mnist_files(dataset: &MnistDataset, image_file_name: &str, label_file_name: &str, format: MnistFormat) -> Result<(), IdxError> {
+    let images = dataset.pixels.to_owned().into_shape((dataset.len(), IMAGE_ROWS, IMAGE_COLUMNS)).unwrap();
+    let images = if format.transposed { images.permuted_axes([0, 2, 1]).as_standard_layout().into_owned() } else { images };
+    let labels = Array1::from_iter(dataset.labels.iter().map(|&label| label + format.first_label));
+
+    write_idx_file(image_file_name, &IdxArray::U8(images.into_dyn()))?;
+    write_idx_file(label_file_name, &IdxArray::U8(labels.into_dyn()))
+}
 
-    Ok(images)
+//Splits the last validation_size images off into their own set, e.g. the classic 50k training and 10k validation
+//split of the 60k MNIST training images, so hyperparameters aren't tuned against the test data
+pub fn split_validation(images: MnistDataset, validation_size: usize) -> (MnistDataset, MnistDataset) {
+    let split = images.len().saturating_sub(validation_size);
+    (images.slice(0, split), images.slice(split, images.len()))
 }
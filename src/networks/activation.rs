@@ -0,0 +1,71 @@
+use std::fmt::{Display, Formatter};
+use clap::ValueEnum;
+use ndarray::{Array2, Axis, Zip};
+use serde::{Deserialize, Serialize};
+use crate::utils::{sigmoid_array, sigmoid_prime_array};
+
+const LEAKY_RELU_SLOPE: f64 = 0.01;
+
+//Activation function of a layer. Each column of the weighted inputs is a separate image, which only matters to softmax
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Activation {
+    Sigmoid,
+    Tanh,
+    Relu,
+    LeakyRelu,
+    Identity,
+    Softmax,
+}
+
+impl Activation {
+    pub fn activate(&self, weighted_inputs: &Array2<f64>) -> Array2<f64> {
+        match self {
+            Activation::Sigmoid => sigmoid_array(weighted_inputs),
+            Activation::Tanh => weighted_inputs.mapv(f64::tanh),
+            Activation::Relu => weighted_inputs.mapv(|z| z.max(0.0)),
+            Activation::LeakyRelu => weighted_inputs.mapv(|z| if z > 0.0 { z } else { LEAKY_RELU_SLOPE * z }),
+            Activation::Identity => weighted_inputs.clone(),
+            Activation::Softmax => {
+                //Shifting by the column max doesn't change the result, but keeps exp from overflowing
+                let max = weighted_inputs.fold_axis(Axis(0), f64::NEG_INFINITY, |&m, &z| m.max(z));
+                let mut exp = weighted_inputs - &max.insert_axis(Axis(0));
+                exp.mapv_inplace(f64::exp);
+                let sum = exp.sum_axis(Axis(0)).insert_axis(Axis(0));
+                exp / sum
+            },
+        }
+    }
+
+    //Carries the gradient of the cost with respect to this layer's activations back to its weighted inputs.
+    //Every activation except softmax is elementwise, so that is just a product with the derivative
+    pub fn backward(&self, weighted_inputs: &Array2<f64>, activations: &Array2<f64>, mut gradient: Array2<f64>) -> Array2<f64> {
+        match self {
+            Activation::Sigmoid => gradient * sigmoid_prime_array(weighted_inputs),
+            Activation::Tanh => {
+                Zip::from(&mut gradient).and(activations).for_each(|g, &a| *g *= 1.0 - a * a);
+                gradient
+            },
+            Activation::Relu => {
+                Zip::from(&mut gradient).and(weighted_inputs).for_each(|g, &z| if z <= 0.0 { *g = 0.0 });
+                gradient
+            },
+            Activation::LeakyRelu => {
+                Zip::from(&mut gradient).and(weighted_inputs).for_each(|g, &z| if z <= 0.0 { *g *= LEAKY_RELU_SLOPE });
+                gradient
+            },
+            Activation::Identity => gradient,
+            Activation::Softmax => {
+                //Jacobian vector product, a_j * (g_j - sum_k g_k a_k), for every column
+                let weighted_sum = (&gradient * activations).sum_axis(Axis(0)).insert_axis(Axis(0));
+                (gradient - weighted_sum) * activations
+            },
+        }
+    }
+}
+
+//As named on the command line
+impl Display for Activation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_possible_value().unwrap().get_name())
+    }
+}
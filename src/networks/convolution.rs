@@ -0,0 +1,302 @@
+//Convolutional and pooling layers
+
+//Feature layers stacked ahead of the fully connected layers, turning the input image into feature maps before the
+//first dense layer sees them flattened. Like the dense matrices, every column of a batch is one image, here flattened
+//channel by channel, then row by row. Convolutions unroll each image into a matrix of receptive fields (im2col) so the
+//whole batch is one matrix product for BLAS, and backpropagate by scattering the field gradients back (col2im)
+
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use clap::ValueEnum;
+use ndarray::{Array2, Axis};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use crate::networks::activation::Activation;
+use crate::networks::initializer::Initialization;
+use crate::networks::layer::{FeatureShape, Layer, LayerKind, LayerParameters};
+
+//feature_maps kernels of kernel_size x kernel_size, each spanning every input channel, moved stride pixels at a time
+//over the input bordered by padding zeros
+pub struct Convolution {
+    input_shape: FeatureShape,
+    output_shape: FeatureShape,
+
+    //One row per feature map, one column per input channel and kernel position
+    weights: Array2<f64>,
+    biases: Array2<f64>,
+    nabla_w: Array2<f64>,
+    nabla_b: Array2<f64>,
+
+    //See field_indices
+    field_indices: Vec<Option<usize>>,
+
+    //Unrolled images of the last forward, side by side
+    fields: Array2<f64>,
+}
+
+//The input element each row of an unrolled image (channel and kernel position) reads at each output position, or None
+//where it reads padding
+pub fn field_indices(input_shape: FeatureShape, output_shape: FeatureShape, kernel_size: usize, stride: usize, padding: usize) -> Vec<Option<usize>> {
+    let field_size = input_shape.channels * kernel_size * kernel_size;
+    let positions = output_shape.rows * output_shape.columns;
+
+    let mut field_indices = Vec::with_capacity(field_size * positions);
+    for channel in 0..input_shape.channels {
+        for kernel_row in 0..kernel_size {
+            for kernel_column in 0..kernel_size {
+                for output_row in 0..output_shape.rows {
+                    for output_column in 0..output_shape.columns {
+                        let row = (output_row * stride + kernel_row).checked_sub(padding).filter(|&row| row < input_shape.rows);
+                        let column = (output_column * stride + kernel_column).checked_sub(padding).filter(|&column| column < input_shape.columns);
+                        field_indices.push(row.zip(column).map(|(row, column)| input_shape.index(channel, row, column)));
+                    }
+                }
+            }
+        }
+    }
+    field_indices
+}
+
+impl Convolution {
+    pub fn new(input_shape: FeatureShape, output_shape: FeatureShape, field_indices: Vec<Option<usize>>, initializer: &Initialization, rng: &mut dyn RngCore) -> Convolution {
+        let num_maps = output_shape.channels;
+        let field_size = field_indices.len() / (output_shape.rows * output_shape.columns);
+        Convolution {
+            input_shape,
+            output_shape,
+            weights: initializer.weights(num_maps, field_size, rng),
+            biases: initializer.biases(num_maps, rng),
+            nabla_w: Array2::zeros((num_maps, field_size)),
+            nabla_b: Array2::zeros((num_maps, 1)),
+            field_indices,
+            fields: Array2::zeros((0, 0)),
+        }
+    }
+
+    fn positions(&self) -> usize {
+        self.output_shape.rows * self.output_shape.columns
+    }
+
+    //One row per feature map with each image's output positions side by side, to one column per image, and back
+    fn maps_to_columns(&self, maps: &Array2<f64>, num_images: usize) -> Array2<f64> {
+        let positions = self.positions();
+        Array2::from_shape_fn((self.output_shape.size(), num_images), |(index, image)| maps[(index / positions, image * positions + index % positions)])
+    }
+
+    fn columns_to_maps(&self, columns: &Array2<f64>) -> Array2<f64> {
+        let positions = self.positions();
+        Array2::from_shape_fn((self.output_shape.channels, columns.ncols() * positions), |(map, index)| columns[(map * positions + index % positions, index / positions)])
+    }
+}
+
+impl Layer for Convolution {
+    fn forward(&mut self, input: &Array2<f64>, _rng: Option<&mut dyn RngCore>) -> Array2<f64> {
+        let num_images = input.ncols();
+        let positions = self.positions();
+        let field_size = self.weights.ncols();
+
+        let mut fields = Array2::zeros((field_size, num_images * positions));
+        for image in 0..num_images {
+            let input = input.column(image);
+            for (i, &index) in self.field_indices.iter().enumerate() {
+                if let Some(index) = index {
+                    fields[(i / positions, image * positions + i % positions)] = input[index];
+                }
+            }
+        }
+
+        let weighted_inputs = self.weights.dot(&fields) + &self.biases;
+        self.fields = fields;
+        self.maps_to_columns(&weighted_inputs, num_images)
+    }
+
+    fn backward(&mut self, gradient: Array2<f64>, propagate: bool) -> Array2<f64> {
+        let num_images = gradient.ncols();
+        let positions = self.positions();
+
+        let delta = self.columns_to_maps(&gradient);
+        self.nabla_w += &delta.dot(&self.fields.t());
+        self.nabla_b += &delta.sum_axis(Axis(1)).insert_axis(Axis(1));
+        if !propagate {
+            return Array2::zeros((0, 0));
+        }
+
+        let field_gradients = self.weights.t().dot(&delta);
+        let mut input_gradient = Array2::zeros((self.input_shape.size(), num_images));
+        for image in 0..num_images {
+            for (i, &index) in self.field_indices.iter().enumerate() {
+                if let Some(index) = index {
+                    input_gradient[(index, image)] += field_gradients[(i / positions, image * positions + i % positions)];
+                }
+            }
+        }
+        input_gradient
+    }
+
+    fn parameters(&mut self) -> Option<LayerParameters<'_>> {
+        Some(LayerParameters { weights: &mut self.weights, biases: &mut self.biases, nabla_w: &mut self.nabla_w, nabla_b: &mut self.nabla_b, regularized: true })
+    }
+
+    fn weights(&self) -> Option<&Array2<f64>> {
+        Some(&self.weights)
+    }
+
+    fn saved(&self) -> Vec<Array2<f64>> {
+        vec![self.biases.clone(), self.weights.clone()]
+    }
+
+    fn restore(&mut self, saved: Vec<Array2<f64>>) {
+        let [biases, weights]: [Array2<f64>; 2] = saved.try_into().expect("a convolutional layer saves its biases and weights");
+        self.biases = biases;
+        self.weights = weights;
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PoolingKind {
+    Max,
+    Average,
+}
+
+//Shrinks every feature map by summarising each non overlapping size x size window as its maximum or average.
+//Rows and columns left over past the last whole window are dropped
+pub struct Pooling {
+    kind: PoolingKind,
+    input_shape: FeatureShape,
+    output_shape: FeatureShape,
+    //The input elements of each output element's window
+    windows: Vec<Vec<usize>>,
+    //Of the last forward, the input element each output element took its maximum from, image by image
+    max_indices: Vec<usize>,
+}
+
+impl Pooling {
+    pub fn new(kind: PoolingKind, input_shape: FeatureShape, output_shape: FeatureShape, size: usize) -> Pooling {
+        let mut windows = Vec::with_capacity(output_shape.size());
+        for channel in 0..output_shape.channels {
+            for output_row in 0..output_shape.rows {
+                for output_column in 0..output_shape.columns {
+                    let mut window = Vec::with_capacity(size * size);
+                    for row in output_row * size..(output_row + 1) * size {
+                        for column in output_column * size..(output_column + 1) * size {
+                            window.push(input_shape.index(channel, row, column));
+                        }
+                    }
+                    windows.push(window);
+                }
+            }
+        }
+
+        Pooling { kind, input_shape, output_shape, windows, max_indices: Vec::new() }
+    }
+}
+
+impl Layer for Pooling {
+    fn forward(&mut self, input: &Array2<f64>, _rng: Option<&mut dyn RngCore>) -> Array2<f64> {
+        let num_images = input.ncols();
+        let mut output = Array2::zeros((self.output_shape.size(), num_images));
+        self.max_indices.clear();
+
+        for image in 0..num_images {
+            let input = input.column(image);
+            for (output_index, window) in self.windows.iter().enumerate() {
+                output[(output_index, image)] = match self.kind {
+                    PoolingKind::Max => {
+                        let max_index = window.iter().copied().fold(window[0], |max, index| if input[index] > input[max] { index } else { max });
+                        self.max_indices.push(max_index);
+                        input[max_index]
+                    },
+                    PoolingKind::Average => window.iter().map(|&index| input[index]).sum::<f64>() / window.len() as f64,
+                };
+            }
+        }
+        output
+    }
+
+    fn backward(&mut self, gradient: Array2<f64>, _propagate: bool) -> Array2<f64> {
+        let num_images = gradient.ncols();
+        let mut input_gradient = Array2::zeros((self.input_shape.size(), num_images));
+
+        for image in 0..num_images {
+            for (output_index, window) in self.windows.iter().enumerate() {
+                let gradient = gradient[(output_index, image)];
+                match self.kind {
+                    PoolingKind::Max => input_gradient[(self.max_indices[image * self.windows.len() + output_index], image)] += gradient,
+                    PoolingKind::Average => {
+                        for &index in window {
+                            input_gradient[(index, image)] += gradient / window.len() as f64;
+                        }
+                    },
+                }
+            }
+        }
+        input_gradient
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum FeatureLayerKind {
+    Convolution { feature_maps: usize, kernel_size: usize, stride: usize, padding: usize, activation: Activation },
+    Pooling { kind: PoolingKind, size: usize },
+}
+
+impl FeatureLayerKind {
+    //A convolution is followed by a separate layer for its activation
+    pub fn layers(&self) -> Vec<LayerKind> {
+        match *self {
+            FeatureLayerKind::Convolution { feature_maps, kernel_size, stride, padding, activation } => vec![
+                LayerKind::Convolution { feature_maps, kernel_size, stride, padding },
+                LayerKind::Activation(activation),
+            ],
+            FeatureLayerKind::Pooling { kind, size } => vec![LayerKind::Pooling { kind, size }],
+        }
+    }
+}
+
+//The shape coming out of a stack of feature layers
+pub fn output_shape(layers: &[FeatureLayerKind], input_shape: FeatureShape) -> Result<FeatureShape, String> {
+    layers.iter().flat_map(FeatureLayerKind::layers).try_fold(input_shape, |shape, layer| layer.output_shape(shape))
+}
+
+//In full in the syntax --features parses
+impl Display for FeatureLayerKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FeatureLayerKind::Convolution { feature_maps, kernel_size, stride, padding, activation } => write!(f, "conv:{}:{}:{}:{}:{}", feature_maps, kernel_size, stride, padding, activation),
+            FeatureLayerKind::Pooling { kind: PoolingKind::Max, size } => write!(f, "maxpool:{}", size),
+            FeatureLayerKind::Pooling { kind: PoolingKind::Average, size } => write!(f, "avgpool:{}", size),
+        }
+    }
+}
+
+//Parses conv:maps:kernel[:stride[:padding]][:activation], e.g. conv:20:5 or conv:32:3:1:1:tanh, with a stride of 1, no
+//padding and relu by default. Or maxpool:size and avgpool:size, e.g. maxpool:2
+impl FromStr for FeatureLayerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts: Vec<&str> = s.split(':').collect();
+        let name = parts.remove(0);
+
+        let activation = match parts.last() {
+            Some(last) if name == "conv" && last.parse::<usize>().is_err() => Some(Activation::from_str(parts.pop().unwrap(), true)?),
+            _ => None,
+        };
+        let numbers = parts.iter().map(|p| p.parse::<usize>().map_err(|e| format!("invalid parameter {} of {}: {}", p, name, e))).collect::<Result<Vec<usize>, String>>()?;
+
+        match (name, numbers.as_slice()) {
+            ("conv", [feature_maps, kernel_size, rest @ ..]) if rest.len() <= 2 => Ok(FeatureLayerKind::Convolution {
+                feature_maps: *feature_maps,
+                kernel_size: *kernel_size,
+                stride: rest.first().copied().unwrap_or(1),
+                padding: rest.get(1).copied().unwrap_or(0),
+                activation: activation.unwrap_or(Activation::Relu),
+            }),
+            ("conv", _) => Err(format!("expected conv:maps:kernel[:stride[:padding]][:activation], got {}", s)),
+            ("maxpool", [size]) => Ok(FeatureLayerKind::Pooling { kind: PoolingKind::Max, size: *size }),
+            ("avgpool", [size]) => Ok(FeatureLayerKind::Pooling { kind: PoolingKind::Average, size: *size }),
+            ("maxpool" | "avgpool", _) => Err(format!("expected {}:size, got {}", name, s)),
+            _ => Err(format!("unknown feature layer {}, expected conv, maxpool or avgpool", name)),
+        }
+    }
+}
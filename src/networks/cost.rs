@@ -0,0 +1,172 @@
+use std::fmt::{Display, Formatter};
+use clap::ValueEnum;
+use ndarray::{Array2, Axis};
+use serde::{Deserialize, Serialize};
+use crate::networks::activation::Activation;
+
+//Stops the costs and general cost gradients taking the log of, or dividing by, zero when an activation saturates
+const EPSILON: f64 = 1e-12;
+
+//How far the desired output's activation must clear every other for the hinge cost to be zero
+const HINGE_MARGIN: f64 = 1.0;
+
+//Focusing parameter of the focal cost. 0 would make it log likelihood, 2 is the usual choice
+const FOCAL_GAMMA: f64 = 2.0;
+
+pub trait Cost {
+    //The cost of a single image, the activation and target vectors being the output layer and desired output
+    fn cost(&self, activation_vector: &Array2<f64>, target_vector: &Array2<f64>) -> f64;
+
+    //The error of the output layer, in terms of the output activations, the desired output, the output weighted inputs
+    //and the activation function of the output layer
+    fn delta(&self, activation_vector: &Array2<f64>, target_vector: &Array2<f64>, weighted_inputs: &Array2<f64>, activation: Activation) -> Array2<f64>;
+}
+
+pub struct QuadraticCost;
+
+impl Cost for QuadraticCost {
+    fn cost(&self, activation_vector: &Array2<f64>, target_vector: &Array2<f64>) -> f64 {
+        0.5 * (activation_vector - target_vector).mapv(|v| v * v).sum()
+    }
+
+    #[inline]
+    fn delta(&self, activation_vector: &Array2<f64>, target_vector: &Array2<f64>, weighted_inputs: &Array2<f64>, activation: Activation) -> Array2<f64> {
+        activation.backward(weighted_inputs, activation_vector, activation_vector - target_vector)
+    }
+}
+
+pub struct CrossEntropyCost;
+
+impl Cost for CrossEntropyCost {
+    fn cost(&self, activation_vector: &Array2<f64>, target_vector: &Array2<f64>) -> f64 {
+        let mut cost = 0.0;
+        for (&a, &y) in activation_vector.iter().zip(target_vector) {
+            let a = a.clamp(EPSILON, 1.0 - EPSILON);
+            cost -= y * a.ln() + (1.0 - y) * (1.0 - a).ln();
+        }
+        cost
+    }
+
+    #[inline]
+    fn delta(&self, activation_vector: &Array2<f64>, target_vector: &Array2<f64>, weighted_inputs: &Array2<f64>, activation: Activation) -> Array2<f64> {
+        match activation {
+            //The sigmoid prime term cancels out, which is what prevents learning slowdown on saturated output neurons
+            Activation::Sigmoid => activation_vector - target_vector,
+            _ => {
+                let gradient = (activation_vector - target_vector) / activation_vector.mapv(|a| (a * (1.0 - a)).max(EPSILON));
+                activation.backward(weighted_inputs, activation_vector, gradient)
+            }
+        }
+    }
+}
+
+//Negative log of the output activation for the desired output, meant to be paired with a softmax output layer
+pub struct LogLikelihoodCost;
+
+impl Cost for LogLikelihoodCost {
+    fn cost(&self, activation_vector: &Array2<f64>, target_vector: &Array2<f64>) -> f64 {
+        let mut cost = 0.0;
+        for (&a, &y) in activation_vector.iter().zip(target_vector) {
+            cost -= y * a.max(EPSILON).ln();
+        }
+        cost
+    }
+
+    #[inline]
+    fn delta(&self, activation_vector: &Array2<f64>, target_vector: &Array2<f64>, weighted_inputs: &Array2<f64>, activation: Activation) -> Array2<f64> {
+        match activation {
+            //Just like cross entropy with sigmoid, the softmax jacobian cancels out
+            Activation::Softmax => activation_vector - target_vector,
+            _ => {
+                let gradient = -target_vector / activation_vector.mapv(|a| a.max(EPSILON));
+                activation.backward(weighted_inputs, activation_vector, gradient)
+            }
+        }
+    }
+}
+
+//Multiclass SVM cost, sum over j of max(0, margin + a_j - a_y) for every output j other than the desired output y.
+//Meant for an identity output layer, it only asks the desired output to win by the margin, not to be a probability
+pub struct HingeCost;
+
+impl HingeCost {
+    //Of every column, each output's margin over the desired output's activation, 0 for the desired output itself
+    fn margins(activation_vector: &Array2<f64>, target_vector: &Array2<f64>) -> Array2<f64> {
+        let desired = (activation_vector * target_vector).sum_axis(Axis(0)).insert_axis(Axis(0));
+        let mut margins = activation_vector - &desired + HINGE_MARGIN;
+        margins.zip_mut_with(target_vector, |m, &y| if y > 0.0 { *m = 0.0 });
+        margins
+    }
+}
+
+impl Cost for HingeCost {
+    fn cost(&self, activation_vector: &Array2<f64>, target_vector: &Array2<f64>) -> f64 {
+        Self::margins(activation_vector, target_vector).iter().map(|m| m.max(0.0)).sum()
+    }
+
+    fn delta(&self, activation_vector: &Array2<f64>, target_vector: &Array2<f64>, weighted_inputs: &Array2<f64>, activation: Activation) -> Array2<f64> {
+        //Each output violating its margin pushes itself down and the desired output up
+        let violations = Self::margins(activation_vector, target_vector).mapv(|m| if m > 0.0 { 1.0 } else { 0.0 });
+        let num_violations = violations.sum_axis(Axis(0)).insert_axis(Axis(0));
+        let gradient = &violations - &(target_vector * &num_violations);
+        activation.backward(weighted_inputs, activation_vector, gradient)
+    }
+}
+
+//Log likelihood with each image's term scaled by (1 - a_y)^gamma, so images the network already classifies confidently
+//add little and training focuses on the hard ones. Meant to be paired with a softmax output layer
+pub struct FocalCost;
+
+impl Cost for FocalCost {
+    fn cost(&self, activation_vector: &Array2<f64>, target_vector: &Array2<f64>) -> f64 {
+        let mut cost = 0.0;
+        for (&a, &y) in activation_vector.iter().zip(target_vector) {
+            let a = a.max(EPSILON);
+            cost -= y * (1.0 - a).powf(FOCAL_GAMMA) * a.ln();
+        }
+        cost
+    }
+
+    fn delta(&self, activation_vector: &Array2<f64>, target_vector: &Array2<f64>, weighted_inputs: &Array2<f64>, activation: Activation) -> Array2<f64> {
+        //d/da of -(1 - a)^gamma ln(a) is gamma (1 - a)^(gamma - 1) ln(a) - (1 - a)^gamma / a
+        let mut gradient = target_vector.clone();
+        gradient.zip_mut_with(activation_vector, |g, &a| {
+            let a = a.max(EPSILON);
+            *g *= FOCAL_GAMMA * (1.0 - a).powf(FOCAL_GAMMA - 1.0) * a.ln() - (1.0 - a).powf(FOCAL_GAMMA) / a;
+        });
+        activation.backward(weighted_inputs, activation_vector, gradient)
+    }
+}
+
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CostKind {
+    Quadratic,
+    CrossEntropy,
+    LogLikelihood,
+    Hinge,
+    Focal,
+}
+
+impl CostKind {
+    pub fn build(self) -> Box<dyn Cost> {
+        match self {
+            CostKind::Quadratic => Box::new(QuadraticCost),
+            CostKind::CrossEntropy => Box::new(CrossEntropyCost),
+            CostKind::LogLikelihood => Box::new(LogLikelihoodCost),
+            CostKind::Hinge => Box::new(HingeCost),
+            CostKind::Focal => Box::new(FocalCost),
+        }
+    }
+
+    //Whether the cost takes the log of the output activations, which then have to be in (0, 1)
+    pub fn needs_probabilities(self) -> bool {
+        matches!(self, CostKind::CrossEntropy | CostKind::LogLikelihood | CostKind::Focal)
+    }
+}
+
+//As named on the command line
+impl Display for CostKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_possible_value().unwrap().get_name())
+    }
+}
@@ -0,0 +1,81 @@
+use std::fmt::{Display, Formatter};
+use ndarray::Array2;
+
+pub struct EvaluationReport {
+    //Rows are the actual label, columns the predicted label
+    pub confusion_matrix: Array2<usize>,
+    pub top_k: usize,
+    pub top_k_accuracy: f64,
+    //Mean of the cost function alone, without any regularization term
+    pub mean_cost: f64,
+    //Indices into the evaluated data of every image predicted wrong
+    pub misclassified: Vec<usize>,
+    //Indexed by label
+    pub class_names: Vec<String>,
+}
+
+impl EvaluationReport {
+    pub fn num_classes(&self) -> usize {
+        self.confusion_matrix.nrows()
+    }
+
+    pub fn total(&self) -> usize {
+        self.confusion_matrix.sum()
+    }
+
+    pub fn accuracy(&self) -> f64 {
+        let correct: usize = self.confusion_matrix.diag().sum();
+        (correct as f64 / self.total() as f64) * 100.0
+    }
+
+    //Of the images predicted as this class, the fraction that were
+    pub fn precision(&self, class: usize) -> f64 {
+        let predicted = self.confusion_matrix.column(class).sum();
+        if predicted == 0 { 0.0 } else { self.confusion_matrix[(class, class)] as f64 / predicted as f64 }
+    }
+
+    //Of the images of this class, the fraction predicted as it
+    pub fn recall(&self, class: usize) -> f64 {
+        let actual = self.confusion_matrix.row(class).sum();
+        if actual == 0 { 0.0 } else { self.confusion_matrix[(class, class)] as f64 / actual as f64 }
+    }
+
+    pub fn f1(&self, class: usize) -> f64 {
+        let precision = self.precision(class);
+        let recall = self.recall(class);
+        if precision + recall == 0.0 { 0.0 } else { 2.0 * precision * recall / (precision + recall) }
+    }
+}
+
+impl Display for EvaluationReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Accuracy: {}% of {} images", self.accuracy(), self.total())?;
+        writeln!(f, "Top {} accuracy: {}%", self.top_k, self.top_k_accuracy)?;
+        writeln!(f, "Mean cost: {}", self.mean_cost)?;
+        writeln!(f, "Misclassified: {} images", self.misclassified.len())?;
+
+        writeln!(f)?;
+        writeln!(f, "Confusion matrix (rows actual, columns predicted):")?;
+        write!(f, "     ")?;
+        for class in 0..self.num_classes() {
+            write!(f, "{:>6}", class)?;
+        }
+        writeln!(f)?;
+        for (class, row) in self.confusion_matrix.rows().into_iter().enumerate() {
+            write!(f, "{:>5}", class)?;
+            for count in row {
+                write!(f, "{:>6}", count)?;
+            }
+            writeln!(f)?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "Class  Precision  Recall     F1  Name")?;
+        for class in 0..self.num_classes() {
+            let name = self.class_names.get(class).map_or("", |name| name.as_str());
+            writeln!(f, "{:>5}  {:>9.4}  {:>6.4}  {:>6.4}  {}", class, self.precision(class), self.recall(class), self.f1(class), name)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,226 @@
+//Gradient checking
+
+//Backpropagation's matrix forms are derived by hand, so this checks them: every parameter is nudged either way by
+//epsilon and the central difference of the cost, (C(p + e) - C(p - e)) / 2e, compared with the gradient backward
+//accumulated. Correct backpropagation agrees to around 1e-7 relative error or better, a wrong equation is usually off by
+//far more than that
+
+use std::fmt::{Display, Formatter};
+use ndarray::Array2;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use crate::networks::cost::Cost;
+use crate::networks::sequential::Sequential;
+
+//Gradients smaller than this are lost in the rounding error of the finite differences, so a parameter matrix whose
+//gradients are all below it either way, such as the biases ahead of a batch normalization, agrees whatever the ratio
+const NEGLIGIBLE_GRADIENT: f64 = 1e-8;
+
+//Dropout draws new masks every forward, so every forward draws them from this same seed to keep the network fixed
+const SEED: u64 = 0;
+
+//Of one layer that learns, numbered in order among only those layers
+#[derive(Debug)]
+pub struct GradientCheck {
+    pub layer: usize,
+    pub biases: f64,
+    pub weights: f64,
+}
+
+impl GradientCheck {
+    pub fn max_error(&self) -> f64 {
+        self.biases.max(self.weights)
+    }
+}
+
+impl Display for GradientCheck {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Layer {}: biases {:.2e}, weights {:.2e}", self.layer, self.biases, self.weights)
+    }
+}
+
+//|a - n| / (|a| + |n|) over a whole parameter matrix, 0 where both are negligible
+fn relative_error(analytic: &Array2<f64>, numerical: &Array2<f64>) -> f64 {
+    let norm = |matrix: &Array2<f64>| matrix.iter().map(|v| v * v).sum::<f64>().sqrt();
+    let (analytic_norm, numerical_norm) = (norm(analytic), norm(numerical));
+    if analytic_norm.max(numerical_norm) < NEGLIGIBLE_GRADIENT {
+        return 0.0;
+    }
+    norm(&(analytic - numerical)) / (analytic_norm + numerical_norm)
+}
+
+//The index-th element, row by row, of a learning layer's biases or weights
+fn parameter(model: &mut Sequential, layer: usize, biases: bool, index: usize) -> &mut f64 {
+    let parameters = model.parameters().swap_remove(layer);
+    let matrix = if biases { parameters.biases } else { parameters.weights };
+    let columns = matrix.ncols();
+    &mut matrix[(index / columns, index % columns)]
+}
+
+fn numerical_gradient(model: &mut Sequential, layer: usize, biases: bool, shape: (usize, usize), epsilon: f64, total_cost: &dyn Fn(&mut Sequential) -> f64) -> Array2<f64> {
+    let mut gradient = Array2::zeros(shape);
+    for (index, g) in gradient.iter_mut().enumerate() {
+        let original = *parameter(model, layer, biases, index);
+        *parameter(model, layer, biases, index) = original + epsilon;
+        let plus = total_cost(model);
+        *parameter(model, layer, biases, index) = original - epsilon;
+        let minus = total_cost(model);
+        *parameter(model, layer, biases, index) = original;
+        *g = (plus - minus) / (2.0 * epsilon);
+    }
+    gradient
+}
+
+//Compares the gradient of the summed cost of the inputs, one image per column, with respect to every parameter of the
+//model. Any regularization is left out, it is added to the gradient separately. Runs two forwards per parameter, so is
+//only meant for small networks and a handful of images. Each forward trains batch normalization's running statistics,
+//so everything the model saves is put back afterwards, leaving only its accumulated gradients changed
+pub fn check_gradients(model: &mut Sequential, cost: &dyn Cost, inputs: &Array2<f64>, targets: &Array2<f64>, epsilon: f64) -> Vec<GradientCheck> {
+    let saved = model.saved();
+    let total_cost = |model: &mut Sequential| {
+        let mut rng = StdRng::seed_from_u64(SEED);
+        cost.cost(model.forward(inputs, Some(&mut rng)), targets)
+    };
+
+    for parameters in model.parameters() {
+        parameters.nabla_b.fill(0.0);
+        parameters.nabla_w.fill(0.0);
+    }
+    model.forward(inputs, Some(&mut StdRng::seed_from_u64(SEED)));
+    model.backward(cost, targets);
+    let analytic: Vec<(Array2<f64>, Array2<f64>)> = model.parameters().into_iter().map(|parameters| (parameters.nabla_b.clone(), parameters.nabla_w.clone())).collect();
+
+    let checks = analytic.into_iter().enumerate().map(|(layer, (nabla_b, nabla_w))| {
+        let numerical_b = numerical_gradient(model, layer, true, nabla_b.dim(), epsilon, &total_cost);
+        let numerical_w = numerical_gradient(model, layer, false, nabla_w.dim(), epsilon, &total_cost);
+        GradientCheck { layer, biases: relative_error(&nabla_b, &numerical_b), weights: relative_error(&nabla_w, &numerical_w) }
+    }).collect();
+
+    model.restore(saved);
+    checks
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::ValueEnum;
+    use ndarray::Array2;
+    use ndarray_rand::rand_distr::Uniform;
+    use ndarray_rand::RandomExt;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use crate::networks::activation::Activation;
+    use crate::networks::convolution::PoolingKind;
+    use crate::networks::cost::CostKind;
+    use crate::networks::initializer::{BiasInitializer, Initialization, ScaledNormalInitializer};
+    use crate::networks::layer::{FeatureShape, LayerKind};
+    use crate::networks::sequential::{Sequential, SequentialBuilder};
+    use super::check_gradients;
+
+    const EPSILON: f64 = 1e-5;
+    const TOLERANCE: f64 = 1e-6;
+    const NUM_IMAGES: usize = 3;
+    const NUM_CLASSES: usize = 3;
+
+    fn shape(channels: usize, rows: usize, columns: usize) -> FeatureShape {
+        FeatureShape { channels, rows, columns }
+    }
+
+    //Random inputs in [0, 1] and one hot targets, one column per image
+    fn batch(input_size: usize, rng: &mut StdRng) -> (Array2<f64>, Array2<f64>) {
+        let inputs = Array2::random_using((input_size, NUM_IMAGES), Uniform::new(0.0, 1.0), rng);
+        let targets = Array2::from_shape_fn((NUM_CLASSES, NUM_IMAGES), |(class, image)| if class == image % NUM_CLASSES { 1.0 } else { 0.0 });
+        (inputs, targets)
+    }
+
+    fn assert_gradients_match(builder: SequentialBuilder, input_shape: FeatureShape, output_activation: Activation, cost: CostKind) {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut model: Sequential = builder.build(output_activation, &Initialization { weights: Box::new(ScaledNormalInitializer), biases: BiasInitializer::Normal }, &mut rng).unwrap();
+        let (inputs, targets) = batch(input_shape.size(), &mut rng);
+
+        for check in check_gradients(&mut model, cost.build().as_ref(), &inputs, &targets, EPSILON) {
+            assert!(check.max_error() < TOLERANCE, "{:?} cost with {:?} output: {}", cost, output_activation, check);
+        }
+    }
+
+    fn dense(hidden_activation: Activation) -> SequentialBuilder {
+        Sequential::builder(shape(4, 1, 1)).dense(5).activation(hidden_activation).dense(NUM_CLASSES)
+    }
+
+    #[test]
+    fn quadratic_cost_with_every_activation() {
+        for &activation in Activation::value_variants() {
+            assert_gradients_match(dense(activation), shape(4, 1, 1), activation, CostKind::Quadratic);
+        }
+    }
+
+    //Cross entropy and log likelihood need outputs in (0, 1)
+    #[test]
+    fn cross_entropy_cost() {
+        for &activation in Activation::value_variants() {
+            for output_activation in [Activation::Sigmoid, Activation::Softmax] {
+                assert_gradients_match(dense(activation), shape(4, 1, 1), output_activation, CostKind::CrossEntropy);
+            }
+        }
+    }
+
+    #[test]
+    fn log_likelihood_cost() {
+        for &activation in Activation::value_variants() {
+            for output_activation in [Activation::Softmax, Activation::Sigmoid] {
+                assert_gradients_match(dense(activation), shape(4, 1, 1), output_activation, CostKind::LogLikelihood);
+            }
+        }
+    }
+
+    #[test]
+    fn hinge_cost() {
+        for &activation in Activation::value_variants() {
+            assert_gradients_match(dense(activation), shape(4, 1, 1), Activation::Identity, CostKind::Hinge);
+            assert_gradients_match(dense(Activation::Tanh), shape(4, 1, 1), activation, CostKind::Hinge);
+        }
+    }
+
+    #[test]
+    fn focal_cost() {
+        for &activation in Activation::value_variants() {
+            for output_activation in [Activation::Softmax, Activation::Sigmoid] {
+                assert_gradients_match(dense(activation), shape(4, 1, 1), output_activation, CostKind::Focal);
+            }
+        }
+    }
+
+    #[test]
+    fn convolution_and_pooling() {
+        for kind in [PoolingKind::Max, PoolingKind::Average] {
+            let input_shape = shape(2, 7, 7);
+            let builder = Sequential::builder(input_shape)
+                .layer(LayerKind::Convolution { feature_maps: 3, kernel_size: 3, stride: 1, padding: 1 }).activation(Activation::Tanh)
+                .layer(LayerKind::Pooling { kind, size: 2 })
+                .layer(LayerKind::Convolution { feature_maps: 2, kernel_size: 2, stride: 2, padding: 0 }).activation(Activation::Sigmoid)
+                .dense(NUM_CLASSES);
+            assert_gradients_match(builder, input_shape, Activation::Softmax, CostKind::LogLikelihood);
+        }
+    }
+
+    fn batch_norm(input_shape: FeatureShape) -> SequentialBuilder {
+        Sequential::builder(input_shape).dropout(0.8).dense(5).batch_norm().activation(Activation::Sigmoid).dropout(0.5).dense(NUM_CLASSES)
+    }
+
+    #[test]
+    fn batch_norm_and_dropout() {
+        let input_shape = shape(4, 1, 1);
+        assert_gradients_match(batch_norm(input_shape), input_shape, Activation::Sigmoid, CostKind::CrossEntropy);
+    }
+
+    #[test]
+    fn running_statistics_are_left_unchanged() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let input_shape = shape(4, 1, 1);
+        let mut model = batch_norm(input_shape).build(Activation::Sigmoid, &Initialization { weights: Box::new(ScaledNormalInitializer), biases: BiasInitializer::Normal }, &mut rng).unwrap();
+        let (inputs, targets) = batch(input_shape.size(), &mut rng);
+
+        let before = model.saved();
+        check_gradients(&mut model, CostKind::CrossEntropy.build().as_ref(), &inputs, &targets, EPSILON);
+        assert_eq!(model.saved(), before);
+    }
+}
@@ -0,0 +1,163 @@
+use std::str::FromStr;
+use clap::ValueEnum;
+use ndarray::{Array2, Axis};
+use ndarray_rand::rand_distr::{StandardNormal, Uniform};
+use ndarray_rand::RandomExt;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+//How a layer's weights are drawn, from its number of inputs (fan in) and neurons (fan out). For a convolution those
+//are the size of its receptive field and its number of feature maps
+pub trait WeightInitializer {
+    fn weights(&self, num_neurons: usize, num_inputs: usize, rng: &mut dyn RngCore) -> Array2<f64>;
+}
+
+fn normal(num_neurons: usize, num_inputs: usize, std_dev: f64, rng: &mut dyn RngCore) -> Array2<f64> {
+    Array2::random_using((num_neurons, num_inputs), StandardNormal, rng).mapv(|v: f64| v * std_dev)
+}
+
+//Standard normal, the book's large weight initialization. Weighted inputs start with a standard deviation of around
+//sqrt(fan in), so sigmoid neurons start out saturated and learn slowly
+pub struct StandardNormalInitializer;
+
+impl WeightInitializer for StandardNormalInitializer {
+    fn weights(&self, num_neurons: usize, num_inputs: usize, rng: &mut dyn RngCore) -> Array2<f64> {
+        Array2::random_using((num_neurons, num_inputs), StandardNormal, rng)
+    }
+}
+
+//LeCun normal: standard normal divided by the sqrt of the number of inputting weights, so weighted inputs don't start saturated
+pub struct ScaledNormalInitializer;
+
+impl WeightInitializer for ScaledNormalInitializer {
+    fn weights(&self, num_neurons: usize, num_inputs: usize, rng: &mut dyn RngCore) -> Array2<f64> {
+        Array2::random_using((num_neurons, num_inputs), StandardNormal, rng).mapv(|v: f64| v / (num_inputs as f64).sqrt())
+    }
+}
+
+//Glorot and Bengio's variance of 2 / (fan in + fan out), keeping both the activations and the gradients from growing or
+//shrinking layer to layer, for tanh and sigmoid layers. Uniform over [-sqrt(6 / (fan in + fan out)), sqrt(...)]
+pub struct XavierUniformInitializer;
+
+impl WeightInitializer for XavierUniformInitializer {
+    fn weights(&self, num_neurons: usize, num_inputs: usize, rng: &mut dyn RngCore) -> Array2<f64> {
+        let limit = (6.0 / (num_inputs + num_neurons) as f64).sqrt();
+        Array2::random_using((num_neurons, num_inputs), Uniform::new_inclusive(-limit, limit), rng)
+    }
+}
+
+//The same variance as XavierUniformInitializer, normally distributed
+pub struct XavierNormalInitializer;
+
+impl WeightInitializer for XavierNormalInitializer {
+    fn weights(&self, num_neurons: usize, num_inputs: usize, rng: &mut dyn RngCore) -> Array2<f64> {
+        normal(num_neurons, num_inputs, (2.0 / (num_inputs + num_neurons) as f64).sqrt(), rng)
+    }
+}
+
+//He (Kaiming) normal: a variance of 2 / fan in, doubling LeCun's to make up for ReLU zeroing half its inputs
+pub struct HeInitializer;
+
+impl WeightInitializer for HeInitializer {
+    fn weights(&self, num_neurons: usize, num_inputs: usize, rng: &mut dyn RngCore) -> Array2<f64> {
+        normal(num_neurons, num_inputs, (2.0 / num_inputs as f64).sqrt(), rng)
+    }
+}
+
+//Orthonormal rows, or columns when there are more neurons than inputs, so the layer preserves the length of whatever
+//it is given. Gram-Schmidt orthonormalization of a standard normal matrix
+pub struct OrthogonalInitializer;
+
+impl WeightInitializer for OrthogonalInitializer {
+    fn weights(&self, num_neurons: usize, num_inputs: usize, rng: &mut dyn RngCore) -> Array2<f64> {
+        let transposed = num_neurons > num_inputs;
+        let (rows, columns) = if transposed { (num_inputs, num_neurons) } else { (num_neurons, num_inputs) };
+
+        let mut weights: Array2<f64> = Array2::random_using((rows, columns), StandardNormal, rng);
+        for i in 0..rows {
+            let (done, mut rest) = weights.view_mut().split_at(Axis(0), i);
+            let mut row = rest.row_mut(0);
+            for previous in done.rows() {
+                let projection = row.dot(&previous);
+                row.scaled_add(-projection, &previous);
+            }
+            let norm = row.dot(&row).sqrt();
+            row /= norm;
+        }
+
+        if transposed { weights.reversed_axes() } else { weights }
+    }
+}
+
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug)]
+pub enum InitializerKind {
+    #[value(name = "large")]
+    StandardNormal,
+    #[value(name = "lecun")]
+    ScaledNormal,
+    XavierUniform,
+    XavierNormal,
+    He,
+    Orthogonal,
+}
+
+impl InitializerKind {
+    pub fn build(self) -> Box<dyn WeightInitializer> {
+        match self {
+            InitializerKind::StandardNormal => Box::new(StandardNormalInitializer),
+            InitializerKind::ScaledNormal => Box::new(ScaledNormalInitializer),
+            InitializerKind::XavierUniform => Box::new(XavierUniformInitializer),
+            InitializerKind::XavierNormal => Box::new(XavierNormalInitializer),
+            InitializerKind::He => Box::new(HeInitializer),
+            InitializerKind::Orthogonal => Box::new(OrthogonalInitializer),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum BiasInitializer {
+    //Standard normal
+    Normal,
+    Zeros,
+    Constant { value: f64 },
+}
+
+impl BiasInitializer {
+    pub fn biases(&self, num_neurons: usize, rng: &mut dyn RngCore) -> Array2<f64> {
+        match *self {
+            BiasInitializer::Normal => Array2::random_using((num_neurons, 1), StandardNormal, rng),
+            BiasInitializer::Zeros => Array2::zeros((num_neurons, 1)),
+            BiasInitializer::Constant { value } => Array2::from_elem((num_neurons, 1), value),
+        }
+    }
+}
+
+//Parses normal, zeros or constant:value, e.g. constant:0.1 to start ReLU neurons active
+impl FromStr for BiasInitializer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "normal" => Ok(BiasInitializer::Normal),
+            None if s == "zeros" => Ok(BiasInitializer::Zeros),
+            Some(("constant", value)) => value.parse().map(|value| BiasInitializer::Constant { value }).map_err(|e| format!("invalid constant bias {}: {}", value, e)),
+            _ => Err(format!("unknown bias initializer {}, expected normal, zeros or constant:value", s)),
+        }
+    }
+}
+
+//The weights and biases of every layer. Draws from rng, so a seeded rng gives the same starting weights every run
+pub struct Initialization {
+    pub weights: Box<dyn WeightInitializer>,
+    pub biases: BiasInitializer,
+}
+
+impl Initialization {
+    pub fn weights(&self, num_neurons: usize, num_inputs: usize, rng: &mut dyn RngCore) -> Array2<f64> {
+        self.weights.weights(num_neurons, num_inputs, rng)
+    }
+
+    pub fn biases(&self, num_neurons: usize, rng: &mut dyn RngCore) -> Array2<f64> {
+        self.biases.biases(num_neurons, rng)
+    }
+}
@@ -0,0 +1,323 @@
+//Layers
+
+//The building blocks of a sequential model. Every layer maps a matrix with one column per image to another, keeping
+//whatever it needs from the last forward to backpropagate through it, so the same code runs one image or a whole batch
+//at a time. Convolution and pooling live in their own module, the rest are here
+
+use ndarray::{Array2, Axis, Zip};
+use rand::{Rng, RngCore};
+use serde::{Deserialize, Serialize};
+use crate::networks::activation::Activation;
+use crate::networks::convolution::{field_indices, Convolution, Pooling, PoolingKind};
+use crate::networks::initializer::Initialization;
+
+//Keeps batch normalization from dividing by zero on units that don't vary across the batch
+const BATCH_NORM_EPSILON: f64 = 1e-5;
+
+//How much of the running mean and variance batch normalization keeps from before each batch
+const BATCH_NORM_MOMENTUM: f64 = 0.9;
+
+//Feature maps x rows x columns. A fully connected layer's output is one channel per neuron of a single pixel
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FeatureShape {
+    pub channels: usize,
+    pub rows: usize,
+    pub columns: usize,
+}
+
+impl FeatureShape {
+    pub fn size(&self) -> usize {
+        self.channels * self.rows * self.columns
+    }
+
+    pub fn index(&self, channel: usize, row: usize, column: usize) -> usize {
+        (channel * self.rows + row) * self.columns + column
+    }
+}
+
+//A layer's learnable parameters along with the gradients accumulated by backward since they were last zeroed. The
+//regularizer decays weights, never biases, and only when regularized
+pub struct LayerParameters<'a> {
+    pub weights: &'a mut Array2<f64>,
+    pub biases: &'a mut Array2<f64>,
+    pub nabla_w: &'a mut Array2<f64>,
+    pub nabla_b: &'a mut Array2<f64>,
+    pub regularized: bool,
+}
+
+pub trait Layer {
+    //rng is Some while training, for dropout to draw its masks from and to have batch normalization use the batch's
+    //own statistics. Keeps whatever backward will need
+    fn forward(&mut self, input: &Array2<f64>, rng: Option<&mut dyn RngCore>) -> Array2<f64>;
+
+    //From the gradient of the cost with respect to the last forward's output, adds to the parameter gradients and
+    //returns the gradient with respect to its input. Without propagate nothing below needs that gradient, so it may
+    //be left empty
+    fn backward(&mut self, gradient: Array2<f64>, propagate: bool) -> Array2<f64>;
+
+    fn parameters(&mut self) -> Option<LayerParameters<'_>> {
+        None
+    }
+
+    //Those of the parameters if they're regularized, for the regularization cost
+    fn weights(&self) -> Option<&Array2<f64>> {
+        None
+    }
+
+    //Everything needed to restore the layer, learned biases then weights first
+    fn saved(&self) -> Vec<Array2<f64>> {
+        Vec::new()
+    }
+
+    //From what saved returned
+    fn restore(&mut self, _saved: Vec<Array2<f64>>) {}
+}
+
+//Every output neuron a weighted sum of every input plus a bias
+pub struct Dense {
+    weights: Array2<f64>,
+    biases: Array2<f64>,
+    nabla_w: Array2<f64>,
+    nabla_b: Array2<f64>,
+    //Of the last forward
+    input: Array2<f64>,
+}
+
+impl Dense {
+    pub fn new(num_neurons: usize, num_inputs: usize, initializer: &Initialization, rng: &mut dyn RngCore) -> Dense {
+        Dense {
+            //Drawn biases then weights, the order the dense layers have always been initialised in
+            biases: initializer.biases(num_neurons, rng),
+            weights: initializer.weights(num_neurons, num_inputs, rng),
+            nabla_w: Array2::zeros((num_neurons, num_inputs)),
+            nabla_b: Array2::zeros((num_neurons, 1)),
+            input: Array2::zeros((0, 0)),
+        }
+    }
+}
+
+impl Layer for Dense {
+    fn forward(&mut self, input: &Array2<f64>, _rng: Option<&mut dyn RngCore>) -> Array2<f64> {
+        self.input = input.clone();
+        self.weights.dot(input) + &self.biases
+    }
+
+    fn backward(&mut self, gradient: Array2<f64>, propagate: bool) -> Array2<f64> {
+        //Summed over the columns, so a batch matrix sums the per image nablas as part of the product
+        self.nabla_b += &gradient.sum_axis(Axis(1)).insert_axis(Axis(1));
+        self.nabla_w += &gradient.dot(&self.input.t());
+        if propagate {
+            self.weights.t().dot(&gradient)
+        } else {
+            Array2::zeros((0, 0))
+        }
+    }
+
+    fn parameters(&mut self) -> Option<LayerParameters<'_>> {
+        Some(LayerParameters { weights: &mut self.weights, biases: &mut self.biases, nabla_w: &mut self.nabla_w, nabla_b: &mut self.nabla_b, regularized: true })
+    }
+
+    fn weights(&self) -> Option<&Array2<f64>> {
+        Some(&self.weights)
+    }
+
+    fn saved(&self) -> Vec<Array2<f64>> {
+        vec![self.biases.clone(), self.weights.clone()]
+    }
+
+    fn restore(&mut self, saved: Vec<Array2<f64>>) {
+        let [biases, weights]: [Array2<f64>; 2] = saved.try_into().expect("a dense layer saves its biases and weights");
+        self.biases = biases;
+        self.weights = weights;
+    }
+}
+
+pub struct ActivationLayer {
+    activation: Activation,
+    //Of the last forward
+    weighted_inputs: Array2<f64>,
+    activations: Array2<f64>,
+}
+
+impl ActivationLayer {
+    pub fn new(activation: Activation) -> ActivationLayer {
+        ActivationLayer { activation, weighted_inputs: Array2::zeros((0, 0)), activations: Array2::zeros((0, 0)) }
+    }
+}
+
+impl Layer for ActivationLayer {
+    fn forward(&mut self, input: &Array2<f64>, _rng: Option<&mut dyn RngCore>) -> Array2<f64> {
+        self.weighted_inputs = input.clone();
+        self.activations = self.activation.activate(input);
+        self.activations.clone()
+    }
+
+    fn backward(&mut self, gradient: Array2<f64>, _propagate: bool) -> Array2<f64> {
+        self.activation.backward(&self.weighted_inputs, &self.activations, gradient)
+    }
+}
+
+//While training, zeroes each unit with probability 1 - keep_probability and scales the kept ones up by
+//1/keep_probability, so the expected output is unchanged and nothing needs rescaling when evaluating without dropout
+pub struct Dropout {
+    keep_probability: f64,
+    //Scaled mask of the last forward while training, one column per image, so no gradient flows back through dropped units
+    mask: Option<Array2<f64>>,
+}
+
+impl Dropout {
+    pub fn new(keep_probability: f64) -> Dropout {
+        Dropout { keep_probability, mask: None }
+    }
+}
+
+impl Layer for Dropout {
+    fn forward(&mut self, input: &Array2<f64>, rng: Option<&mut dyn RngCore>) -> Array2<f64> {
+        let keep_probability = self.keep_probability;
+        self.mask = rng.map(|rng| Array2::from_shape_fn(input.dim(), |_| if rng.gen_bool(keep_probability) { 1.0 / keep_probability } else { 0.0 }));
+        match &self.mask {
+            Some(mask) => input * mask,
+            None => input.clone(),
+        }
+    }
+
+    fn backward(&mut self, gradient: Array2<f64>, _propagate: bool) -> Array2<f64> {
+        match &self.mask {
+            Some(mask) => gradient * mask,
+            None => gradient,
+        }
+    }
+}
+
+//Standardizes each unit by its mean and variance over the batch while training, or over every batch so far when
+//evaluating, then scales and shifts it by a learned gamma and beta. Needs batches of more than one image, a lone image
+//standardizes to all zeros
+pub struct BatchNorm {
+    gamma: Array2<f64>,
+    beta: Array2<f64>,
+    nabla_gamma: Array2<f64>,
+    nabla_beta: Array2<f64>,
+    running_mean: Array2<f64>,
+    running_variance: Array2<f64>,
+    //Of the last forward while training
+    normalized: Array2<f64>,
+    inverse_std_dev: Array2<f64>,
+}
+
+impl BatchNorm {
+    pub fn new(size: usize) -> BatchNorm {
+        BatchNorm {
+            gamma: Array2::ones((size, 1)),
+            beta: Array2::zeros((size, 1)),
+            nabla_gamma: Array2::zeros((size, 1)),
+            nabla_beta: Array2::zeros((size, 1)),
+            running_mean: Array2::zeros((size, 1)),
+            running_variance: Array2::ones((size, 1)),
+            normalized: Array2::zeros((0, 0)),
+            inverse_std_dev: Array2::zeros((0, 0)),
+        }
+    }
+}
+
+impl Layer for BatchNorm {
+    fn forward(&mut self, input: &Array2<f64>, rng: Option<&mut dyn RngCore>) -> Array2<f64> {
+        if rng.is_none() {
+            let inverse_std_dev = self.running_variance.mapv(|v| 1.0 / (v + BATCH_NORM_EPSILON).sqrt());
+            return (input - &self.running_mean) * inverse_std_dev * &self.gamma + &self.beta;
+        }
+
+        let mean = input.mean_axis(Axis(1)).unwrap().insert_axis(Axis(1));
+        let centred = input - &mean;
+        let variance = centred.mapv(|x| x * x).mean_axis(Axis(1)).unwrap().insert_axis(Axis(1));
+        self.running_mean = &self.running_mean * BATCH_NORM_MOMENTUM + &mean * (1.0 - BATCH_NORM_MOMENTUM);
+        self.running_variance = &self.running_variance * BATCH_NORM_MOMENTUM + &variance * (1.0 - BATCH_NORM_MOMENTUM);
+
+        self.inverse_std_dev = variance.mapv(|v| 1.0 / (v + BATCH_NORM_EPSILON).sqrt());
+        self.normalized = centred * &self.inverse_std_dev;
+        &self.normalized * &self.gamma + &self.beta
+    }
+
+    fn backward(&mut self, gradient: Array2<f64>, _propagate: bool) -> Array2<f64> {
+        self.nabla_beta += &gradient.sum_axis(Axis(1)).insert_axis(Axis(1));
+        self.nabla_gamma += &(&gradient * &self.normalized).sum_axis(Axis(1)).insert_axis(Axis(1));
+
+        //Every image's output depends on the whole batch through the mean and variance:
+        //dx = (1/sigma) * (dx^ - mean(dx^) - x^ * mean(dx^ * x^)), with dx^ = gamma * dy
+        let normalized_gradient = gradient * &self.gamma;
+        let mean_gradient = normalized_gradient.mean_axis(Axis(1)).unwrap().insert_axis(Axis(1));
+        let mean_projection = (&normalized_gradient * &self.normalized).mean_axis(Axis(1)).unwrap().insert_axis(Axis(1));
+        let mut input_gradient = normalized_gradient - &mean_gradient;
+        Zip::from(&mut input_gradient).and(&self.normalized).and_broadcast(&mean_projection).and_broadcast(&self.inverse_std_dev).for_each(|g, &x, &p, &s| {
+            *g = (*g - x * p) * s;
+        });
+        input_gradient
+    }
+
+    //Decaying the scale towards 0 would fight the normalization it follows, so neither it nor the shift is regularized
+    fn parameters(&mut self) -> Option<LayerParameters<'_>> {
+        Some(LayerParameters { weights: &mut self.gamma, biases: &mut self.beta, nabla_w: &mut self.nabla_gamma, nabla_b: &mut self.nabla_beta, regularized: false })
+    }
+
+    fn saved(&self) -> Vec<Array2<f64>> {
+        vec![self.beta.clone(), self.gamma.clone(), self.running_mean.clone(), self.running_variance.clone()]
+    }
+
+    fn restore(&mut self, saved: Vec<Array2<f64>>) {
+        let [beta, gamma, running_mean, running_variance]: [Array2<f64>; 4] = saved.try_into().expect("a batch normalization layer saves its beta, gamma, running mean and running variance");
+        self.beta = beta;
+        self.gamma = gamma;
+        self.running_mean = running_mean;
+        self.running_variance = running_variance;
+    }
+}
+
+//Every kind of layer, from which a sequential model builds each one once it knows the shape of its input
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LayerKind {
+    Dense { neurons: usize },
+    Activation(Activation),
+    Dropout { keep_probability: f64 },
+    BatchNorm,
+    Convolution { feature_maps: usize, kernel_size: usize, stride: usize, padding: usize },
+    Pooling { kind: PoolingKind, size: usize },
+}
+
+impl LayerKind {
+    pub fn output_shape(&self, input_shape: FeatureShape) -> Result<FeatureShape, String> {
+        match *self {
+            LayerKind::Dense { neurons } => Ok(FeatureShape { channels: neurons, rows: 1, columns: 1 }),
+            LayerKind::Activation(_) | LayerKind::Dropout { .. } | LayerKind::BatchNorm => Ok(input_shape),
+            LayerKind::Convolution { feature_maps, kernel_size, stride, padding } => {
+                if feature_maps == 0 || kernel_size == 0 || stride == 0 {
+                    return Err("convolution feature maps, kernel size and stride must all be at least 1".to_string());
+                }
+                let (rows, columns) = (input_shape.rows + 2 * padding, input_shape.columns + 2 * padding);
+                if kernel_size > rows || kernel_size > columns {
+                    return Err(format!("{}x{} kernel doesn't fit in {}x{} padded input", kernel_size, kernel_size, rows, columns));
+                }
+                Ok(FeatureShape { channels: feature_maps, rows: (rows - kernel_size) / stride + 1, columns: (columns - kernel_size) / stride + 1 })
+            },
+            LayerKind::Pooling { size, .. } => {
+                if size == 0 || size > input_shape.rows || size > input_shape.columns {
+                    return Err(format!("{}x{} pooling doesn't fit in {}x{} input", size, size, input_shape.rows, input_shape.columns));
+                }
+                Ok(FeatureShape { channels: input_shape.channels, rows: input_shape.rows / size, columns: input_shape.columns / size })
+            },
+        }
+    }
+
+    pub fn build(&self, input_shape: FeatureShape, initializer: &Initialization, rng: &mut dyn RngCore) -> Result<Box<dyn Layer>, String> {
+        let output_shape = self.output_shape(input_shape)?;
+        Ok(match *self {
+            LayerKind::Dense { neurons } => Box::new(Dense::new(neurons, input_shape.size(), initializer, rng)),
+            LayerKind::Activation(activation) => Box::new(ActivationLayer::new(activation)),
+            LayerKind::Dropout { keep_probability } => Box::new(Dropout::new(keep_probability)),
+            LayerKind::BatchNorm => Box::new(BatchNorm::new(input_shape.size())),
+            LayerKind::Convolution { kernel_size, stride, padding, .. } => {
+                let field_indices = field_indices(input_shape, output_shape, kernel_size, stride, padding);
+                Box::new(Convolution::new(input_shape, output_shape, field_indices, initializer, rng))
+            },
+            LayerKind::Pooling { kind, size } => Box::new(Pooling::new(kind, input_shape, output_shape, size)),
+        })
+    }
+}
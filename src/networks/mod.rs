@@ -1,2 +1,233 @@
+pub mod activation;
+pub mod convolution;
+pub mod cost;
+pub mod evaluation;
+pub mod gradient_check;
+pub mod initializer;
+pub mod layer;
+pub mod normalization;
+pub mod optimizer;
+pub mod regularizer;
+pub mod schedule;
+pub mod sequential;
+pub mod training;
+pub mod network;
 pub mod network1;
 pub mod network2;
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use clap::ValueEnum;
+use ndarray::Array2;
+use rand::prelude::SliceRandom;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use crate::mnist::{MnistDataset, MnistImage};
+use crate::networks::evaluation::EvaluationReport;
+use crate::networks::network::NetworkConfig;
+use crate::networks::normalization::Normalization;
+use crate::networks::schedule::LearningRateSchedule;
+use crate::networks::training::{EpochMetrics, TrainingHistory, TrainingOptions};
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Implementation {
+    Network1,
+    Network2,
+}
+
+pub trait Network {
+    //Returns the output layer activations
+    fn feed_forward(&mut self, input_array: &Array2<f64>) -> &Array2<f64>;
+
+    //n is the size of the whole training set, which regularization is scaled by. Dropout masks are drawn from rng
+    fn train_batch(&mut self, batch: &[MnistImage], learning_rate: f64, n: usize, rng: &mut dyn RngCore);
+
+    //Cost of a single image from its output layer activations, without regularization
+    fn output_cost(&self, activation_vector: &Array2<f64>, target_vector: &Array2<f64>) -> f64;
+
+    //The regularization term of the cost, for a data set of size n
+    fn regularization_cost(&self, n: usize) -> f64;
+
+    fn to_saved(&self) -> SavedNetwork;
+
+    //Replaces the weights and biases with those of a network of the same structure
+    fn restore(&mut self, saved: SavedNetwork);
+
+    fn predict(&mut self, input_array: &Array2<f64>) -> u8 {
+        let activation_vector = self.feed_forward(input_array);
+
+        //Find what it selected
+        let mut predicted_number = 0;
+        let mut predicted_certainty = f64::NEG_INFINITY;
+        for (index, &certainty) in activation_vector.column(0).iter().enumerate() {
+            if certainty > predicted_certainty {
+                predicted_number = index as u8;
+                predicted_certainty = certainty;
+            }
+        }
+
+        predicted_number
+    }
+
+    fn evaluate(&mut self, testing_data: &MnistDataset) -> f64 {
+        let mut correct_counter = 0;
+        let mut input = Array2::zeros((testing_data.image_size(), 1));
+
+        for image in testing_data.views() {
+            image.image_into(&mut input);
+            if self.predict(&input) == image.label {
+                correct_counter += 1;
+            }
+        }
+
+        (correct_counter as f64 / testing_data.len() as f64) * 100.0
+    }
+
+    //Mean cost over the data, including the regularization term
+    fn total_cost(&mut self, data: &MnistDataset) -> f64 {
+        let mut cost = 0.0;
+        let mut input = Array2::zeros((data.image_size(), 1));
+        let mut target = Array2::zeros((data.num_classes(), 1));
+
+        for image in data.views() {
+            image.image_into(&mut input);
+            image.label_array_into(&mut target);
+            let activation_vector = self.feed_forward(&input).clone();
+            cost += self.output_cost(&activation_vector, &target);
+        }
+
+        cost / data.len() as f64 + self.regularization_cost(data.len())
+    }
+
+    //Everything evaluate throws away: which digits were confused for which, how often the label was at least
+    //among the top_k most certain outputs, the mean cost and which images were wrong
+    fn evaluate_report(&mut self, testing_data: &MnistDataset, top_k: usize) -> EvaluationReport {
+        let mut confusion_matrix: Option<Array2<usize>> = None;
+        let mut top_k_counter = 0;
+        let mut cost = 0.0;
+        let mut misclassified = Vec::new();
+        let mut input = Array2::zeros((testing_data.image_size(), 1));
+        let mut target = Array2::zeros((testing_data.num_classes(), 1));
+
+        for (index, image) in testing_data.views().enumerate() {
+            image.image_into(&mut input);
+            image.label_array_into(&mut target);
+            let activation_vector = self.feed_forward(&input).clone();
+            let certainties = activation_vector.column(0);
+            let num_classes = certainties.len();
+
+            let mut ranked: Vec<usize> = (0..num_classes).collect();
+            ranked.sort_by(|&a, &b| certainties[b].total_cmp(&certainties[a]));
+
+            let label = image.label as usize;
+            let predicted = ranked[0];
+
+            confusion_matrix.get_or_insert_with(|| Array2::zeros((num_classes, num_classes)))[(label, predicted)] += 1;
+            if ranked.iter().take(top_k).any(|&class| class == label) {
+                top_k_counter += 1;
+            }
+            if predicted != label {
+                misclassified.push(index);
+            }
+            cost += self.output_cost(&activation_vector, &target);
+        }
+
+        let confusion_matrix = confusion_matrix.unwrap_or_else(|| Array2::zeros((0, 0)));
+        EvaluationReport {
+            class_names: (0..confusion_matrix.nrows()).map(|class| class.to_string()).collect(),
+            confusion_matrix,
+            top_k,
+            top_k_accuracy: (top_k_counter as f64 / testing_data.len() as f64) * 100.0,
+            mean_cost: cost / testing_data.len() as f64,
+            misclassified,
+        }
+    }
+
+    //Evaluates against validation_data after every epoch, along with whichever other metrics options.monitor asks for.
+    //With early stopping, training stops once that many epochs pass without the validation accuracy improving, and
+    //the weights from the best epoch are restored. Training also stops early if the learning rate schedule finishes.
+    //Shuffling, augmentation and dropout draw from rng, so a seeded rng repeats the run exactly
+    fn train(&mut self, training_data: &MnistDataset, validation_data: &MnistDataset, schedule: &mut dyn LearningRateSchedule, options: &TrainingOptions, rng: &mut dyn RngCore) -> TrainingHistory {
+        let n = training_data.len();
+        //The dataset stays put, only the order batches are drawn in is shuffled
+        let mut order: Vec<usize> = (0..n).collect();
+        let mut history = TrainingHistory::default();
+
+        //Best validation accuracy so far, the epoch it was reached in and a snapshot of the weights at that point
+        let mut best: Option<(f64, usize, SavedNetwork)> = None;
+
+        println!("Performance from random: {}%", self.evaluate(validation_data));
+
+        for epoch in 0..options.epochs {
+            let learning_rate = schedule.learning_rate(epoch);
+            order.shuffle(rng);
+
+            for batch in order.chunks(options.batch_size) {
+                let mut batch = training_data.select(batch);
+                for image in batch.iter_mut() {
+                    options.augmentations.apply(image, rng);
+                }
+                self.train_batch(&batch, learning_rate, n, rng);
+            }
+
+            let metrics = EpochMetrics {
+                epoch,
+                learning_rate,
+                evaluation_accuracy: self.evaluate(validation_data),
+                evaluation_cost: options.monitor.evaluation_cost.then(|| self.total_cost(validation_data)),
+                training_accuracy: options.monitor.training_accuracy.then(|| self.evaluate(training_data)),
+                training_cost: options.monitor.training_cost.then(|| self.total_cost(training_data)),
+            };
+            let accuracy = metrics.evaluation_accuracy;
+            println!("{}", metrics);
+            history.epochs.push(metrics);
+
+            if let Some(patience) = options.early_stopping {
+                match &best {
+                    Some((best_accuracy, best_epoch, _)) if accuracy <= *best_accuracy => {
+                        if epoch - best_epoch >= patience {
+                            println!("No improvement in {} epochs, stopping early", patience);
+                            break;
+                        }
+                    },
+                    _ => best = Some((accuracy, epoch, self.to_saved())),
+                }
+            }
+
+            schedule.end_epoch(accuracy);
+            if schedule.finished() {
+                println!("Learning rate schedule finished");
+                break;
+            }
+        }
+
+        if let Some((accuracy, epoch, saved)) = best {
+            println!("Restoring weights from epoch {}: {}%", epoch, accuracy);
+            self.restore(saved);
+            history.restored_epoch = Some(epoch);
+        }
+
+        history
+    }
+}
+
+//Everything needed to reconstruct a trained network
+#[derive(Serialize, Deserialize)]
+pub struct SavedNetwork {
+    pub config: NetworkConfig,
+    pub normalization: Normalization,
+    //What each layer of the model saved, in order
+    pub layers: Vec<Vec<Array2<f64>>>,
+}
+
+impl SavedNetwork {
+    pub fn save(&self, file_name: &str) -> Result<(), serde_pickle::Error> {
+        let mut writer = BufWriter::new(File::create(file_name)?);
+        serde_pickle::to_writer(&mut writer, self, serde_pickle::SerOptions::new())
+    }
+
+    pub fn load(file_name: &str) -> Result<SavedNetwork, serde_pickle::Error> {
+        let reader = BufReader::new(File::open(file_name)?);
+        serde_pickle::from_reader(reader, serde_pickle::DeOptions::new())
+    }
+}
@@ -0,0 +1,253 @@
+//Configurable network
+
+//The shared implementation behind every preset. Structure is defined in initialisation, and the cost,
+//weight initialisation, regularization, optimizer and each layer's activation function are chosen by the NetworkConfig
+//The config is built into a sequential model of layers: any input dropout, any convolutional and pooling feature layers, then the fully
+//connected ones, which see their flattened output, each followed by any batch normalization, its activation and any dropout
+//Backpropagation either runs once for each image, iterating over the batch, or once for the whole batch
+//stacked into a matrix with one column per image, so BLAS sees matrix-matrix products
+
+use std::fmt::{Display, Formatter};
+use clap::ValueEnum;
+use ndarray::{concatenate, Array2, ArrayView2, Axis};
+use rand::{thread_rng, RngCore};
+use serde::{Deserialize, Serialize};
+use crate::dataset::DatasetKind;
+use crate::mnist::{MnistImage, IMAGE_COLUMNS, IMAGE_ROWS};
+use crate::networks::activation::Activation;
+use crate::networks::convolution::{self, FeatureLayerKind};
+use crate::networks::cost::{Cost, CostKind};
+use crate::networks::gradient_check::{self, GradientCheck};
+use crate::networks::initializer::{BiasInitializer, Initialization, InitializerKind};
+use crate::networks::layer::FeatureShape;
+use crate::networks::normalization::Normalization;
+use crate::networks::optimizer::{Optimizer, OptimizerKind};
+use crate::networks::regularizer::{Regularizer, RegularizerKind};
+use crate::networks::sequential::Sequential;
+use crate::networks::{Network, SavedNetwork};
+
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug)]
+pub enum BackpropMode {
+    PerImage,
+    Matrix,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct NetworkConfig {
+    //Neurons per fully connected layer, the first being the size of the image or of the feature layers' output
+    pub structure: Vec<usize>,
+    //One per layer after the input layer
+    pub activations: Vec<Activation>,
+    pub backprop: BackpropMode,
+    pub cost: CostKind,
+    //Of the weights
+    pub initializer: InitializerKind,
+    pub bias_initializer: BiasInitializer,
+    pub regularizer: RegularizerKind,
+    pub optimizer: OptimizerKind,
+    //Velocity coefficient of the momentum based optimizers
+    pub momentum: f64,
+    //What the network was trained to classify
+    pub dataset: DatasetKind,
+    //Chance each input pixel and each hidden neuron is kept, rather than dropped out, for each image trained on
+    pub input_keep_probability: f64,
+    pub hidden_keep_probability: f64,
+    //Convolutional and pooling layers, in order, between the image and the fully connected layers
+    pub features: Vec<FeatureLayerKind>,
+    //Batch normalization of every hidden layer's weighted inputs, before its activation
+    pub batch_norm: bool,
+}
+
+//A summary in the terms of the train options, leaving out how the network was trained
+impl Display for NetworkConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let join = |items: Vec<String>| items.join(",");
+        writeln!(f, "Dataset: {}", self.dataset)?;
+        if !self.features.is_empty() {
+            writeln!(f, "Features: {}", join(self.features.iter().map(|feature| feature.to_string()).collect()))?;
+        }
+        writeln!(f, "Layers: {}", join(self.structure.iter().map(|neurons| neurons.to_string()).collect()))?;
+        writeln!(f, "Activations: {}", join(self.activations.iter().map(|activation| activation.to_string()).collect()))?;
+        if self.batch_norm {
+            writeln!(f, "Batch normalized")?;
+        }
+        write!(f, "Cost: {}", self.cost)?;
+        if let RegularizerKind::L2 { lambda } = self.regularizer {
+            write!(f, ", L2 regularized with lambda {}", lambda)?;
+        }
+        Ok(())
+    }
+}
+
+//Every image as a single channel feature map
+pub const IMAGE_SHAPE: FeatureShape = FeatureShape { channels: 1, rows: IMAGE_ROWS, columns: IMAGE_COLUMNS };
+
+impl NetworkConfig {
+    //Initial weights and biases are drawn from rng
+    fn model(&self, rng: &mut dyn RngCore) -> Result<Sequential, String> {
+        let feature_size = convolution::output_shape(&self.features, IMAGE_SHAPE)?.size();
+        if feature_size != self.structure[0] {
+            return Err(format!("the first layer must take the {} outputs of the feature layers, not {}", feature_size, self.structure[0]));
+        }
+
+        let mut builder = Sequential::builder(IMAGE_SHAPE).dropout(self.input_keep_probability);
+        for feature in &self.features {
+            for layer in feature.layers() {
+                builder = builder.layer(layer);
+            }
+        }
+
+        let num_layers = self.structure.len() - 1;
+        for (layer_index, (&num_neurons, &activation)) in self.structure[1..].iter().zip(&self.activations).enumerate() {
+            builder = builder.dense(num_neurons);
+            //The output layer's activation is the model's own
+            if layer_index + 1 < num_layers {
+                if self.batch_norm {
+                    builder = builder.batch_norm();
+                }
+                builder = builder.activation(activation).dropout(self.hidden_keep_probability);
+            }
+        }
+
+        let initialization = Initialization { weights: self.initializer.build(), biases: self.bias_initializer };
+        builder.build(self.activations[num_layers - 1], &initialization, rng)
+    }
+}
+
+pub struct ConfigurableNetwork {
+    config: NetworkConfig,
+
+    cost: Box<dyn Cost>,
+    regularizer: Box<dyn Regularizer>,
+    optimizer: Box<dyn Optimizer>,
+
+    //Applied to every input before the first layer
+    normalization: Normalization,
+
+    model: Sequential,
+}
+
+impl ConfigurableNetwork {
+    //Initial weights and biases are drawn from rng
+    pub fn new(config: NetworkConfig, rng: &mut dyn RngCore) -> Box<Self> {
+        let model = config.model(rng).unwrap_or_else(|e| panic!("invalid network config {:?}: {}", config, e));
+
+        Box::new(Self {
+            cost: config.cost.build(),
+            regularizer: config.regularizer.build(),
+            optimizer: config.optimizer.build(config.momentum),
+
+            config,
+
+            normalization: Normalization::default(),
+
+            model,
+        })
+    }
+
+    pub fn set_normalization(&mut self, normalization: Normalization) {
+        self.normalization = normalization;
+    }
+
+    pub fn from_saved(saved: SavedNetwork) -> Box<Self> {
+        //The initial weights are replaced straight away, so needn't be reproducible
+        let mut network = Self::new(saved.config.clone(), &mut thread_rng());
+        network.restore(saved);
+        network
+    }
+
+    //Normalized copies of the images, one per column
+    fn normalized(&self, input: &Array2<f64>) -> Array2<f64> {
+        let mut input = input.clone();
+        self.normalization.apply(&mut input);
+        input
+    }
+
+    //Sums every image's nabla into the layers' nablas, one image at a time
+    fn accumulate_per_image(&mut self, batch: &[MnistImage], rng: &mut dyn RngCore) {
+        for image in batch {
+            let input = self.normalized(&image.image);
+            self.model.forward(&input, Some(rng));
+            self.model.backward(self.cost.as_ref(), &image.label_array);
+        }
+    }
+
+    //Sums every image's nabla into the layers' nablas with the whole batch at once. Each column of the stacked
+    //matrices is one image, so the per image equations carry over unchanged, and the product of the delta
+    //matrix with the transposed previous activations sums the per image weight nablas as part of the product
+    fn accumulate_matrix(&mut self, batch: &[MnistImage], rng: &mut dyn RngCore) {
+        let images: Vec<ArrayView2<f64>> = batch.iter().map(|image| image.image.view()).collect();
+        let labels: Vec<ArrayView2<f64>> = batch.iter().map(|image| image.label_array.view()).collect();
+        let mut input_matrix = concatenate(Axis(1), &images).unwrap();
+        let target_matrix = concatenate(Axis(1), &labels).unwrap();
+
+        self.normalization.apply(&mut input_matrix);
+        self.model.forward(&input_matrix, Some(rng));
+        self.model.backward(self.cost.as_ref(), &target_matrix);
+    }
+
+    //Checks backpropagation against finite differences over the images, normalized as for training. See gradient_check
+    pub fn check_gradients(&mut self, images: &[MnistImage], epsilon: f64) -> Vec<GradientCheck> {
+        let inputs: Vec<ArrayView2<f64>> = images.iter().map(|image| image.image.view()).collect();
+        let labels: Vec<ArrayView2<f64>> = images.iter().map(|image| image.label_array.view()).collect();
+        let input_matrix = self.normalized(&concatenate(Axis(1), &inputs).unwrap());
+        let target_matrix = concatenate(Axis(1), &labels).unwrap();
+
+        gradient_check::check_gradients(&mut self.model, self.cost.as_ref(), &input_matrix, &target_matrix, epsilon)
+    }
+}
+
+impl Network for ConfigurableNetwork {
+    fn feed_forward(&mut self, input_array: &Array2<f64>) -> &Array2<f64> {
+        let input = self.normalized(input_array);
+        self.model.forward(&input, None)
+    }
+
+    fn train_batch(&mut self, batch: &[MnistImage], learning_rate: f64, n: usize, rng: &mut dyn RngCore) {
+        //Reset the nabla allocations
+        for parameters in self.model.parameters() {
+            parameters.nabla_b.fill(0.0);
+            parameters.nabla_w.fill(0.0);
+        }
+
+        match self.config.backprop {
+            BackpropMode::PerImage => self.accumulate_per_image(batch, rng),
+            BackpropMode::Matrix => self.accumulate_matrix(batch, rng),
+        }
+
+        let batch_scalar = 1.0 / batch.len() as f64;
+
+        //Biases are even optimizer parameter indices, weights odd
+        self.optimizer.begin_batch();
+        for (layer_index, parameters) in self.model.parameters().into_iter().enumerate() {
+            *parameters.nabla_b *= batch_scalar;
+            self.optimizer.update(2 * layer_index, parameters.biases, parameters.nabla_b, learning_rate);
+            *parameters.nabla_w *= batch_scalar;
+            if parameters.regularized {
+                self.regularizer.regularize(parameters.nabla_w, parameters.weights, n);
+            }
+            self.optimizer.update(2 * layer_index + 1, parameters.weights, parameters.nabla_w, learning_rate);
+        }
+    }
+
+    fn output_cost(&self, activation_vector: &Array2<f64>, target_vector: &Array2<f64>) -> f64 {
+        self.cost.cost(activation_vector, target_vector)
+    }
+
+    fn regularization_cost(&self, n: usize) -> f64 {
+        self.regularizer.cost(&self.model.weights(), n)
+    }
+
+    fn to_saved(&self) -> SavedNetwork {
+        SavedNetwork {
+            config: self.config.clone(),
+            normalization: self.normalization.clone(),
+            layers: self.model.saved(),
+        }
+    }
+
+    fn restore(&mut self, saved: SavedNetwork) {
+        self.normalization = saved.normalization;
+        self.model.restore(saved.layers);
+    }
+}
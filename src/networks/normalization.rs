@@ -0,0 +1,428 @@
+//Input normalization
+
+//Transforms the [0, 1] pixels of every image before the first layer sees them. The z-score and whitening statistics are
+//fitted to the training set and saved along with the weights, so a loaded network transforms its input identically
+
+use std::fmt::{Display, Formatter};
+use clap::ValueEnum;
+use ndarray::{s, Array1, Array2, Axis, Zip};
+use serde::{Deserialize, Serialize};
+use crate::mnist::MnistDataset;
+
+//Added to every pixel's variance before dividing by its square root. Pixels that barely vary across the training set,
+//such as the nearly always blank border, are then divided by around 0.1 rather than their near zero standard
+//deviation, so any ink an augmentation moves there is scaled up at most tenfold rather than blown up
+const PER_PIXEL_EPSILON: f64 = 0.01;
+
+//A training set of identical images is divided by 1 rather than its zero standard deviation
+const MIN_STD_DEV: f64 = 1e-8;
+
+//Added to every variance before whitening divides by its square root, for the same reason as PER_PIXEL_EPSILON
+const WHITENING_EPSILON: f64 = 0.1;
+
+//Images are converted to floats this many at a time while fitting, rather than the whole training set at once
+const FIT_CHUNK_SIZE: usize = 1000;
+
+//QL iterations allowed for each eigenvalue, as in tql2. Two or three usually suffice
+const MAX_QL_ITERATIONS: usize = 30;
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum NormalizationKind {
+    //Pixels in [0, 1], as loaded
+    #[default]
+    Unit,
+    //Pixels in [-1, 1]
+    Symmetric,
+    //Each pixel standardized by its own training set mean and standard deviation
+    PerPixel,
+    //Every pixel standardized by the mean and standard deviation of all training set pixels
+    Global,
+    //Centred and rotated onto the principal components of the training set, each scaled to unit variance
+    Pca,
+}
+
+impl NormalizationKind {
+    //Fails if the training set's principal components can't be found
+    pub fn fit(self, training_data: &MnistDataset) -> Result<Normalization, String> {
+        Ok(match self {
+            NormalizationKind::Unit => Normalization::Unit,
+            NormalizationKind::Symmetric => Normalization::Symmetric,
+            NormalizationKind::PerPixel => {
+                let (mean, variance) = pixel_moments(training_data);
+                let std_dev = variance.mapv(|v| (v + PER_PIXEL_EPSILON).sqrt());
+                Normalization::PerPixel { mean: mean.insert_axis(Axis(1)), std_dev: std_dev.insert_axis(Axis(1)) }
+            },
+            NormalizationKind::Global => {
+                let (mean, variance) = pixel_moments(training_data);
+                let global_mean = mean.mean().unwrap_or(0.0);
+                //Total variance about the global mean, from each pixel's variance about its own mean
+                let global_variance = (&variance + &mean.mapv(|m| (m - global_mean).powi(2))).mean().unwrap_or(1.0);
+                let std_dev = if global_variance.sqrt() < MIN_STD_DEV { 1.0 } else { global_variance.sqrt() };
+                Normalization::Global { mean: global_mean, std_dev }
+            },
+            NormalizationKind::Pca => {
+                let (mean, covariance) = pixel_covariance(training_data);
+                Normalization::Pca { mean: mean.insert_axis(Axis(1)), whitening: whitening(covariance, WHITENING_EPSILON)? }
+            },
+        })
+    }
+}
+
+//As named on the command line
+impl Display for NormalizationKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_possible_value().unwrap().get_name())
+    }
+}
+
+//A fitted normalization. Column vectors, so they broadcast across a batch matrix of one image per column as well
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub enum Normalization {
+    #[default]
+    Unit,
+    Symmetric,
+    PerPixel { mean: Array2<f64>, std_dev: Array2<f64> },
+    Global { mean: f64, std_dev: f64 },
+    Pca { mean: Array2<f64>, whitening: Array2<f64> },
+}
+
+impl Normalization {
+    pub fn kind(&self) -> NormalizationKind {
+        match self {
+            Normalization::Unit => NormalizationKind::Unit,
+            Normalization::Symmetric => NormalizationKind::Symmetric,
+            Normalization::PerPixel { .. } => NormalizationKind::PerPixel,
+            Normalization::Global { .. } => NormalizationKind::Global,
+            Normalization::Pca { .. } => NormalizationKind::Pca,
+        }
+    }
+
+    //Transforms every column of input, each one image
+    pub fn apply(&self, input: &mut Array2<f64>) {
+        match self {
+            Normalization::Unit => {},
+            Normalization::Symmetric => input.mapv_inplace(|x| 2.0 * x - 1.0),
+            Normalization::PerPixel { mean, std_dev } => {
+                *input -= mean;
+                *input /= std_dev;
+            },
+            Normalization::Global { mean, std_dev } => input.mapv_inplace(|x| (x - mean) / std_dev),
+            Normalization::Pca { mean, whitening } => {
+                *input -= mean;
+                *input = whitening.dot(input);
+            },
+        }
+    }
+}
+
+//Every chunk of the training set as floats in [0, 1], one image per row
+fn float_chunks(data: &MnistDataset) -> impl Iterator<Item = Array2<f64>> + '_ {
+    (0..data.len()).step_by(FIT_CHUNK_SIZE).map(|start| {
+        let end = (start + FIT_CHUNK_SIZE).min(data.len());
+        data.pixels().slice(s![start..end, ..]).mapv(|x| x as f64 / 255.)
+    })
+}
+
+//Mean and variance of each pixel
+fn pixel_moments(data: &MnistDataset) -> (Array1<f64>, Array1<f64>) {
+    let size = data.pixels().ncols();
+    let mut sum = Array1::zeros(size);
+    let mut sum_of_squares = Array1::zeros(size);
+    for chunk in float_chunks(data) {
+        sum += &chunk.sum_axis(Axis(0));
+        sum_of_squares += &chunk.mapv(|x| x * x).sum_axis(Axis(0));
+    }
+
+    let n = data.len().max(1) as f64;
+    let mean = sum / n;
+    let variance = Zip::from(&sum_of_squares).and(&mean).map_collect(|&s, &m| (s / n - m * m).max(0.0));
+    (mean, variance)
+}
+
+//Mean of each pixel, and the covariance matrix between every pair of pixels
+fn pixel_covariance(data: &MnistDataset) -> (Array1<f64>, Array2<f64>) {
+    let size = data.pixels().ncols();
+    let mut sum = Array1::zeros(size);
+    let mut products = Array2::zeros((size, size));
+    for chunk in float_chunks(data) {
+        sum += &chunk.sum_axis(Axis(0));
+        products += &chunk.t().dot(&chunk);
+    }
+
+    let n = data.len().max(1) as f64;
+    let mean = sum / n;
+    let outer = mean.view().insert_axis(Axis(1)).dot(&mean.view().insert_axis(Axis(0)));
+    (mean, products / n - outer)
+}
+
+//Rotates onto the eigenvectors of the covariance, one per row, each scaled by 1 / sqrt(eigenvalue + epsilon)
+fn whitening(covariance: Array2<f64>, epsilon: f64) -> Result<Array2<f64>, String> {
+    let (eigenvalues, eigenvectors) = symmetric_eigen(covariance)?;
+    let scale = eigenvalues.mapv(|v| 1.0 / (v.max(0.0) + epsilon).sqrt());
+    Ok(eigenvectors.t().to_owned() * scale.insert_axis(Axis(1)))
+}
+
+//Eigenvalues and eigenvectors, one per column, of a symmetric matrix: Householder reduction to tridiagonal form, then
+//the QL algorithm with implicit shifts, after the EISPACK routines tred2 and tql2
+fn symmetric_eigen(matrix: Array2<f64>) -> Result<(Array1<f64>, Array2<f64>), String> {
+    if matrix.iter().any(|v| !v.is_finite()) {
+        return Err("the covariance matrix has non-finite entries".to_string());
+    }
+
+    let n = matrix.nrows();
+    let mut v = matrix;
+    let mut d = Array1::zeros(n);
+    let mut e = Array1::zeros(n);
+    if n == 0 {
+        return Ok((d, v));
+    }
+
+    //Householder reduction, leaving the diagonal in d, the subdiagonal in e and the accumulated transformations in v
+    for j in 0..n {
+        d[j] = v[(n - 1, j)];
+    }
+    for i in (1..n).rev() {
+        let scale: f64 = (0..i).map(|k| d[k].abs()).sum();
+        let mut h = 0.0;
+        if scale == 0.0 {
+            e[i] = d[i - 1];
+            for j in 0..i {
+                d[j] = v[(i - 1, j)];
+                v[(i, j)] = 0.0;
+                v[(j, i)] = 0.0;
+            }
+        } else {
+            for k in 0..i {
+                d[k] /= scale;
+                h += d[k] * d[k];
+            }
+            let f = d[i - 1];
+            let g = if f > 0.0 { -h.sqrt() } else { h.sqrt() };
+            e[i] = scale * g;
+            h -= f * g;
+            d[i - 1] = f - g;
+            for j in 0..i {
+                e[j] = 0.0;
+            }
+
+            for j in 0..i {
+                let f = d[j];
+                v[(j, i)] = f;
+                let mut g = e[j] + v[(j, j)] * f;
+                for k in j + 1..i {
+                    g += v[(k, j)] * d[k];
+                    e[k] += v[(k, j)] * f;
+                }
+                e[j] = g;
+            }
+            let mut f = 0.0;
+            for j in 0..i {
+                e[j] /= h;
+                f += e[j] * d[j];
+            }
+            let hh = f / (h + h);
+            for j in 0..i {
+                e[j] -= hh * d[j];
+            }
+            for j in 0..i {
+                let (f, g) = (d[j], e[j]);
+                for k in j..i {
+                    v[(k, j)] -= f * e[k] + g * d[k];
+                }
+                d[j] = v[(i - 1, j)];
+                v[(i, j)] = 0.0;
+            }
+        }
+        d[i] = h;
+    }
+
+    for i in 0..n - 1 {
+        v[(n - 1, i)] = v[(i, i)];
+        v[(i, i)] = 1.0;
+        let h = d[i + 1];
+        if h != 0.0 {
+            for k in 0..=i {
+                d[k] = v[(k, i + 1)] / h;
+            }
+            for j in 0..=i {
+                let g: f64 = (0..=i).map(|k| v[(k, i + 1)] * v[(k, j)]).sum();
+                for k in 0..=i {
+                    v[(k, j)] -= g * d[k];
+                }
+            }
+        }
+        for k in 0..=i {
+            v[(k, i + 1)] = 0.0;
+        }
+    }
+    for j in 0..n {
+        d[j] = v[(n - 1, j)];
+        v[(n - 1, j)] = 0.0;
+    }
+    v[(n - 1, n - 1)] = 1.0;
+
+    //QL iterations on the tridiagonal matrix
+    for i in 1..n {
+        e[i - 1] = e[i];
+    }
+    e[n - 1] = 0.0;
+
+    let mut f = 0.0;
+    let mut tst1: f64 = 0.0;
+    for l in 0..n {
+        //Find a small subdiagonal element to split the matrix at
+        tst1 = tst1.max(d[l].abs() + e[l].abs());
+        let mut m = l;
+        while m < n - 1 && e[m].abs() > f64::EPSILON * tst1 {
+            m += 1;
+        }
+
+        if m > l {
+            for iteration in 1.. {
+                if iteration > MAX_QL_ITERATIONS {
+                    return Err(format!("eigenvalue {} did not converge in {} QL iterations", l, MAX_QL_ITERATIONS));
+                }
+
+                let g = d[l];
+                let mut p = (d[l + 1] - g) / (2.0 * e[l]);
+                let mut r = p.hypot(1.0);
+                if p < 0.0 {
+                    r = -r;
+                }
+                d[l] = e[l] / (p + r);
+                d[l + 1] = e[l] * (p + r);
+                let dl1 = d[l + 1];
+                let mut h = g - d[l];
+                for i in l + 2..n {
+                    d[i] -= h;
+                }
+                f += h;
+
+                p = d[m];
+                let (mut c, mut c2, mut c3) = (1.0, 1.0, 1.0);
+                let el1 = e[l + 1];
+                let (mut s, mut s2) = (0.0, 0.0);
+                for i in (l..m).rev() {
+                    c3 = c2;
+                    c2 = c;
+                    s2 = s;
+                    let g = c * e[i];
+                    h = c * p;
+                    r = p.hypot(e[i]);
+                    e[i + 1] = s * r;
+                    s = e[i] / r;
+                    c = p / r;
+                    p = c * d[i] - s * g;
+                    d[i + 1] = h + s * (c * g + s * d[i]);
+
+                    for k in 0..n {
+                        let h = v[(k, i + 1)];
+                        v[(k, i + 1)] = s * v[(k, i)] + c * h;
+                        v[(k, i)] = c * v[(k, i)] - s * h;
+                    }
+                }
+                p = -s * s2 * c3 * el1 * e[l] / dl1;
+                e[l] = s * p;
+                d[l] = c * p;
+
+                if e[l].abs() <= f64::EPSILON * tst1 {
+                    break;
+                }
+            }
+        }
+        d[l] += f;
+        e[l] = 0.0;
+    }
+
+    Ok((d, v))
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{Array1, Array2, Axis};
+    use ndarray_rand::rand_distr::Uniform;
+    use ndarray_rand::RandomExt;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use crate::mnist::MnistDataset;
+    use super::{pixel_covariance, symmetric_eigen, whitening, Normalization, NormalizationKind, PER_PIXEL_EPSILON, WHITENING_EPSILON};
+
+    const TOLERANCE: f64 = 1e-9;
+
+    fn assert_close(actual: &Array2<f64>, expected: &Array2<f64>) {
+        let difference = (actual - expected).mapv(f64::abs).fold(0.0, |max: f64, &v| max.max(v));
+        assert!(difference < TOLERANCE, "off by {:e}:\n{}\nexpected\n{}", difference, actual, expected);
+    }
+
+    //Pixels drawn from a few shared sources, so neighbouring pixels are correlated as they are in real images
+    fn correlated_dataset(rng: &mut StdRng) -> MnistDataset {
+        let sources: Array2<f64> = Array2::random_using((500, 3), Uniform::new(0.0, 1.0), rng);
+        let mixing = Array2::random_using((3, 5), Uniform::new(0.0, 1.0), rng);
+        let pixels = (sources.dot(&mixing) * 255.0 / 3.0).mapv(|v| v.round() as u8);
+        MnistDataset::new(pixels, vec![0; 500], 10)
+    }
+
+    //Ink shifted onto a pixel that is blank in all but one training image stays within a small multiple of its range
+    #[test]
+    fn per_pixel_bounds_rarely_inked_pixels() {
+        let mut pixels = Array2::zeros((1000, 2));
+        pixels[(0, 0)] = 255;
+        pixels.column_mut(1).iter_mut().enumerate().for_each(|(i, pixel)| *pixel = if i % 2 == 0 { 255 } else { 0 });
+        let dataset = MnistDataset::new(pixels, vec![0; 1000], 10);
+
+        let normalization = NormalizationKind::PerPixel.fit(&dataset).unwrap();
+        let mut inked = Array2::ones((2, 1));
+        normalization.apply(&mut inked);
+        assert!(inked[(0, 0)] > 0.0 && inked[(0, 0)] <= 1.0 / PER_PIXEL_EPSILON.sqrt(), "rarely inked pixel became {}", inked[(0, 0)]);
+        //A pixel varying as much as any can is standardized almost exactly
+        assert!((inked[(1, 0)] - 1.0).abs() < 0.05, "evenly inked pixel became {}", inked[(1, 0)]);
+    }
+
+    #[test]
+    fn eigenvectors_reconstruct_symmetric_matrices() {
+        let random = Array2::random_using((6, 6), Uniform::new(-1.0, 1.0), &mut StdRng::seed_from_u64(1));
+        let matrices = [
+            &random + &random.t(),
+            Array2::from_diag(&Array1::from(vec![2.0, 0.0, 2.0, -1.0])),
+            Array2::from_elem((1, 1), 3.0),
+        ];
+
+        for matrix in matrices {
+            let (eigenvalues, eigenvectors) = symmetric_eigen(matrix.clone()).unwrap();
+            let n = matrix.nrows();
+            assert_close(&eigenvectors.t().dot(&eigenvectors), &Array2::eye(n));
+            assert_close(&eigenvectors.dot(&Array2::from_diag(&eigenvalues)).dot(&eigenvectors.t()), &matrix);
+        }
+    }
+
+    #[test]
+    fn non_finite_matrices_are_rejected() {
+        let mut matrix = Array2::eye(3);
+        matrix[(0, 1)] = f64::NAN;
+        matrix[(1, 0)] = f64::NAN;
+        assert!(symmetric_eigen(matrix).is_err());
+    }
+
+    #[test]
+    fn pca_whitening_gives_identity_covariance() {
+        let dataset = correlated_dataset(&mut StdRng::seed_from_u64(2));
+        let (_, covariance) = pixel_covariance(&dataset);
+
+        //Without the epsilon every principal component is scaled to exactly unit variance
+        let unregularized = whitening(covariance.clone(), 0.0).unwrap();
+        assert_close(&unregularized.dot(&covariance).dot(&unregularized.t()), &Array2::eye(covariance.nrows()));
+
+        //The fitted normalization shrinks each component's variance from 1 to eigenvalue / (eigenvalue + epsilon)
+        let normalization = NormalizationKind::Pca.fit(&dataset).unwrap();
+        assert!(matches!(normalization, Normalization::Pca { .. }));
+        let mut images = dataset.pixels().t().mapv(|x| x as f64 / 255.);
+        normalization.apply(&mut images);
+        let whitened_mean = images.mean_axis(Axis(1)).unwrap();
+        assert_close(&whitened_mean.insert_axis(Axis(1)), &Array2::zeros((covariance.nrows(), 1)));
+
+        let (eigenvalues, _) = symmetric_eigen(covariance).unwrap();
+        let whitened_covariance = images.dot(&images.t()) / dataset.len() as f64;
+        let expected = Array2::from_diag(&eigenvalues.mapv(|v| v.max(0.0) / (v.max(0.0) + WHITENING_EPSILON)));
+        assert_close(&whitened_covariance, &expected);
+    }
+}
@@ -0,0 +1,181 @@
+use clap::ValueEnum;
+use ndarray::{Array2, Zip};
+use serde::{Deserialize, Serialize};
+
+//Keeps the adaptive optimizers from dividing by zero on parameters that have had no gradient yet
+const EPSILON: f64 = 1e-8;
+
+pub trait Optimizer {
+    //Called once per batch, before any parameter is updated
+    fn begin_batch(&mut self) {}
+
+    //Steps one parameter matrix against its batch averaged gradient. index uniquely identifies the parameter
+    //matrix, so that optimizers can keep state (velocities, moment estimates) per parameter between batches
+    fn update(&mut self, index: usize, parameters: &mut Array2<f64>, gradient: &Array2<f64>, learning_rate: f64);
+}
+
+//Per parameter state, allocated the first time a parameter is seen so optimizers don't need to know the structure
+fn state_for<'a>(states: &'a mut Vec<Array2<f64>>, index: usize, parameters: &Array2<f64>) -> &'a mut Array2<f64> {
+    if states.len() <= index {
+        states.resize(index + 1, Array2::zeros((0,0)));
+    }
+    if states[index].dim() != parameters.dim() {
+        states[index] = Array2::zeros(parameters.dim());
+    }
+    &mut states[index]
+}
+
+//Plain stochastic gradient descent
+pub struct Sgd;
+
+impl Optimizer for Sgd {
+    #[inline]
+    fn update(&mut self, _index: usize, parameters: &mut Array2<f64>, gradient: &Array2<f64>, learning_rate: f64) {
+        parameters.scaled_add(-learning_rate, gradient);
+    }
+}
+
+//v = mu * v - lr * g, p += v
+pub struct Momentum {
+    pub coefficient: f64,
+    velocities: Vec<Array2<f64>>,
+}
+
+impl Momentum {
+    pub fn new(coefficient: f64) -> Self {
+        Self { coefficient, velocities: Vec::new() }
+    }
+}
+
+impl Optimizer for Momentum {
+    fn update(&mut self, index: usize, parameters: &mut Array2<f64>, gradient: &Array2<f64>, learning_rate: f64) {
+        let velocity = state_for(&mut self.velocities, index, parameters);
+        *velocity *= self.coefficient;
+        velocity.scaled_add(-learning_rate, gradient);
+        *parameters += &*velocity;
+    }
+}
+
+//Nesterov momentum, in the form that only needs the gradient at the current parameters rather than at the look ahead
+//point: p += -mu * v_previous + (1 + mu) * v
+pub struct Nesterov {
+    pub coefficient: f64,
+    velocities: Vec<Array2<f64>>,
+}
+
+impl Nesterov {
+    pub fn new(coefficient: f64) -> Self {
+        Self { coefficient, velocities: Vec::new() }
+    }
+}
+
+impl Optimizer for Nesterov {
+    fn update(&mut self, index: usize, parameters: &mut Array2<f64>, gradient: &Array2<f64>, learning_rate: f64) {
+        let mu = self.coefficient;
+        let velocity = state_for(&mut self.velocities, index, parameters);
+        Zip::from(parameters).and(velocity).and(gradient).for_each(|p, v, &g| {
+            let previous_velocity = *v;
+            *v = mu * *v - learning_rate * g;
+            *p += -mu * previous_velocity + (1.0 + mu) * *v;
+        });
+    }
+}
+
+//Per parameter learning rates, scaled down by the sum of all past squared gradients
+#[derive(Default)]
+pub struct AdaGrad {
+    caches: Vec<Array2<f64>>,
+}
+
+impl Optimizer for AdaGrad {
+    fn update(&mut self, index: usize, parameters: &mut Array2<f64>, gradient: &Array2<f64>, learning_rate: f64) {
+        let cache = state_for(&mut self.caches, index, parameters);
+        Zip::from(parameters).and(cache).and(gradient).for_each(|p, c, &g| {
+            *c += g * g;
+            *p -= learning_rate * g / (c.sqrt() + EPSILON);
+        });
+    }
+}
+
+//AdaGrad with a decaying average of squared gradients instead of a sum, so the learning rate doesn't vanish
+pub struct RmsProp {
+    pub decay: f64,
+    caches: Vec<Array2<f64>>,
+}
+
+impl RmsProp {
+    pub fn new(decay: f64) -> Self {
+        Self { decay, caches: Vec::new() }
+    }
+}
+
+impl Optimizer for RmsProp {
+    fn update(&mut self, index: usize, parameters: &mut Array2<f64>, gradient: &Array2<f64>, learning_rate: f64) {
+        let decay = self.decay;
+        let cache = state_for(&mut self.caches, index, parameters);
+        Zip::from(parameters).and(cache).and(gradient).for_each(|p, c, &g| {
+            *c = decay * *c + (1.0 - decay) * g * g;
+            *p -= learning_rate * g / (c.sqrt() + EPSILON);
+        });
+    }
+}
+
+//Decaying averages of both the gradient and squared gradient, bias corrected for their zero initialisation
+pub struct Adam {
+    pub beta1: f64,
+    pub beta2: f64,
+    time_step: i32,
+    first_moments: Vec<Array2<f64>>,
+    second_moments: Vec<Array2<f64>>,
+}
+
+impl Adam {
+    pub fn new(beta1: f64, beta2: f64) -> Self {
+        Self { beta1, beta2, time_step: 0, first_moments: Vec::new(), second_moments: Vec::new() }
+    }
+}
+
+impl Optimizer for Adam {
+    fn begin_batch(&mut self) {
+        self.time_step += 1;
+    }
+
+    fn update(&mut self, index: usize, parameters: &mut Array2<f64>, gradient: &Array2<f64>, learning_rate: f64) {
+        let (beta1, beta2) = (self.beta1, self.beta2);
+        let step_size = learning_rate * (1.0 - beta2.powi(self.time_step)).sqrt() / (1.0 - beta1.powi(self.time_step));
+
+        let first_moment = state_for(&mut self.first_moments, index, parameters);
+        let second_moment = state_for(&mut self.second_moments, index, parameters);
+        Zip::from(parameters).and(first_moment).and(second_moment).and(gradient).for_each(|p, m, v, &g| {
+            *m = beta1 * *m + (1.0 - beta1) * g;
+            *v = beta2 * *v + (1.0 - beta2) * g * g;
+            *p -= step_size * *m / (v.sqrt() + EPSILON);
+        });
+    }
+}
+
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug)]
+pub enum OptimizerKind {
+    Sgd,
+    Momentum,
+    Nesterov,
+    #[value(name = "adagrad")]
+    AdaGrad,
+    #[value(name = "rmsprop")]
+    RmsProp,
+    Adam,
+}
+
+impl OptimizerKind {
+    //momentum is the velocity coefficient of Momentum and Nesterov, the others use their usual default hyperparameters
+    pub fn build(self, momentum: f64) -> Box<dyn Optimizer> {
+        match self {
+            OptimizerKind::Sgd => Box::new(Sgd),
+            OptimizerKind::Momentum => Box::new(Momentum::new(momentum)),
+            OptimizerKind::Nesterov => Box::new(Nesterov::new(momentum)),
+            OptimizerKind::AdaGrad => Box::new(AdaGrad::default()),
+            OptimizerKind::RmsProp => Box::new(RmsProp::new(0.9)),
+            OptimizerKind::Adam => Box::new(Adam::new(0.9, 0.999)),
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use ndarray::Array2;
+use serde::{Deserialize, Serialize};
+
+pub trait Regularizer {
+    //Adds the gradient of the regularization term to the batch averaged weight gradient. n is the size of the whole training set
+    fn regularize(&self, gradient: &mut Array2<f64>, weights: &Array2<f64>, n: usize);
+
+    //The regularization term added to the total cost
+    fn cost(&self, weight_matrices: &[&Array2<f64>], n: usize) -> f64;
+}
+
+pub struct NoRegularization;
+
+impl Regularizer for NoRegularization {
+    #[inline]
+    fn regularize(&self, _gradient: &mut Array2<f64>, _weights: &Array2<f64>, _n: usize) {}
+
+    fn cost(&self, _weight_matrices: &[&Array2<f64>], _n: usize) -> f64 {
+        0.0
+    }
+}
+
+//Weight decay: the gradient of (lambda / 2n) * sum(w^2)
+pub struct L2Regularization {
+    pub lambda: f64,
+}
+
+impl Regularizer for L2Regularization {
+    #[inline]
+    fn regularize(&self, gradient: &mut Array2<f64>, weights: &Array2<f64>, n: usize) {
+        gradient.scaled_add(self.lambda / n as f64, weights);
+    }
+
+    fn cost(&self, weight_matrices: &[&Array2<f64>], n: usize) -> f64 {
+        let sum_of_squares: f64 = weight_matrices.iter().map(|w| w.iter().map(|v| v * v).sum::<f64>()).sum();
+        0.5 * (self.lambda / n as f64) * sum_of_squares
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub enum RegularizerKind {
+    None,
+    L2 { lambda: f64 },
+}
+
+impl RegularizerKind {
+    pub fn build(self) -> Box<dyn Regularizer> {
+        match self {
+            RegularizerKind::None => Box::new(NoRegularization),
+            RegularizerKind::L2 { lambda } => Box::new(L2Regularization { lambda }),
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use std::f64::consts::PI;
+use clap::ValueEnum;
+
+pub trait LearningRateSchedule {
+    //The learning rate to train the given epoch with
+    fn learning_rate(&self, epoch: usize) -> f64;
+
+    //Called at the end of every epoch with that epoch's validation accuracy
+    fn end_epoch(&mut self, _accuracy: f64) {}
+
+    //Whether the learning rate has decayed as far as the schedule intends, which ends training
+    fn finished(&self) -> bool {
+        false
+    }
+}
+
+pub struct Constant {
+    pub learning_rate: f64,
+}
+
+impl LearningRateSchedule for Constant {
+    fn learning_rate(&self, _epoch: usize) -> f64 {
+        self.learning_rate
+    }
+}
+
+//Multiplies the learning rate by factor every step_epochs epochs
+pub struct StepDecay {
+    pub initial_learning_rate: f64,
+    pub factor: f64,
+    pub step_epochs: usize,
+}
+
+impl LearningRateSchedule for StepDecay {
+    fn learning_rate(&self, epoch: usize) -> f64 {
+        self.initial_learning_rate * self.factor.powi((epoch / self.step_epochs) as i32)
+    }
+}
+
+//Multiplies the learning rate by factor every epoch
+pub struct ExponentialDecay {
+    pub initial_learning_rate: f64,
+    pub factor: f64,
+}
+
+impl LearningRateSchedule for ExponentialDecay {
+    fn learning_rate(&self, epoch: usize) -> f64 {
+        self.initial_learning_rate * self.factor.powi(epoch as i32)
+    }
+}
+
+//Follows half a cosine wave from the initial learning rate down towards zero over the whole training run
+pub struct CosineAnnealing {
+    pub initial_learning_rate: f64,
+    pub epochs: usize,
+}
+
+impl LearningRateSchedule for CosineAnnealing {
+    fn learning_rate(&self, epoch: usize) -> f64 {
+        0.5 * self.initial_learning_rate * (1.0 + (PI * epoch as f64 / self.epochs as f64).cos())
+    }
+}
+
+//Multiplies the learning rate by factor whenever validation accuracy hasn't improved in patience epochs, finishing
+//once it has fallen to 1/128th of the initial learning rate. With a factor of 0.5 this is the schedule from chapter 3
+pub struct Plateau {
+    pub factor: f64,
+    pub patience: usize,
+    minimum_learning_rate: f64,
+    learning_rate: f64,
+    best_accuracy: f64,
+    epochs_since_best: usize,
+}
+
+impl Plateau {
+    pub fn new(initial_learning_rate: f64, factor: f64, patience: usize) -> Self {
+        Self {
+            factor,
+            patience,
+            minimum_learning_rate: initial_learning_rate / 128.0,
+            learning_rate: initial_learning_rate,
+            best_accuracy: f64::NEG_INFINITY,
+            epochs_since_best: 0,
+        }
+    }
+}
+
+impl LearningRateSchedule for Plateau {
+    fn learning_rate(&self, _epoch: usize) -> f64 {
+        self.learning_rate
+    }
+
+    fn end_epoch(&mut self, accuracy: f64) {
+        if accuracy > self.best_accuracy {
+            self.best_accuracy = accuracy;
+            self.epochs_since_best = 0;
+        } else {
+            self.epochs_since_best += 1;
+            if self.epochs_since_best >= self.patience {
+                self.learning_rate *= self.factor;
+                self.epochs_since_best = 0;
+            }
+        }
+    }
+
+    fn finished(&self) -> bool {
+        self.learning_rate <= self.minimum_learning_rate
+    }
+}
+
+//Ramps linearly up to the wrapped schedule's learning rate over the first epochs
+pub struct Warmup {
+    pub epochs: usize,
+    pub schedule: Box<dyn LearningRateSchedule>,
+}
+
+impl LearningRateSchedule for Warmup {
+    fn learning_rate(&self, epoch: usize) -> f64 {
+        let learning_rate = self.schedule.learning_rate(epoch);
+        if epoch < self.epochs {
+            learning_rate * (epoch + 1) as f64 / (self.epochs + 1) as f64
+        } else {
+            learning_rate
+        }
+    }
+
+    fn end_epoch(&mut self, accuracy: f64) {
+        self.schedule.end_epoch(accuracy);
+    }
+
+    fn finished(&self) -> bool {
+        self.schedule.finished()
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum ScheduleKind {
+    Constant,
+    Step,
+    Exponential,
+    Cosine,
+    Plateau,
+}
+
+impl ScheduleKind {
+    //factor defaults to halving for step and plateau, and to 0.95 per epoch for exponential
+    pub fn build(self, learning_rate: f64, factor: Option<f64>, step_epochs: usize, patience: usize, warmup_epochs: usize, epochs: usize) -> Box<dyn LearningRateSchedule> {
+        let schedule: Box<dyn LearningRateSchedule> = match self {
+            ScheduleKind::Constant => Box::new(Constant { learning_rate }),
+            ScheduleKind::Step => Box::new(StepDecay { initial_learning_rate: learning_rate, factor: factor.unwrap_or(0.5), step_epochs }),
+            ScheduleKind::Exponential => Box::new(ExponentialDecay { initial_learning_rate: learning_rate, factor: factor.unwrap_or(0.95) }),
+            ScheduleKind::Cosine => Box::new(CosineAnnealing { initial_learning_rate: learning_rate, epochs }),
+            ScheduleKind::Plateau => Box::new(Plateau::new(learning_rate, factor.unwrap_or(0.5), patience)),
+        };
+
+        if warmup_epochs > 0 {
+            Box::new(Warmup { epochs: warmup_epochs, schedule })
+        } else {
+            schedule
+        }
+    }
+}
@@ -0,0 +1,128 @@
+//Sequential model
+
+//A stack of layers each feeding the next, ending with the output layer's weighted inputs. The output activation is
+//kept apart from the layers so the cost can fold its derivative into the output delta, as cross entropy does to the
+//sigmoid's and log likelihood to the softmax's
+
+use ndarray::Array2;
+use rand::RngCore;
+use crate::networks::activation::Activation;
+use crate::networks::cost::Cost;
+use crate::networks::initializer::Initialization;
+use crate::networks::layer::{FeatureShape, Layer, LayerKind, LayerParameters};
+
+pub struct Sequential {
+    layers: Vec<Box<dyn Layer>>,
+    output_activation: Activation,
+    //Nothing below the first layer that learns needs a gradient
+    first_learning_layer: usize,
+
+    //Of the last forward
+    weighted_inputs: Array2<f64>,
+    activations: Array2<f64>,
+}
+
+impl Sequential {
+    pub fn builder(input_shape: FeatureShape) -> SequentialBuilder {
+        SequentialBuilder { input_shape, layers: Vec::new() }
+    }
+
+    //Returns the output activations, one column per image. rng is Some while training, see Layer::forward
+    pub fn forward(&mut self, input: &Array2<f64>, mut rng: Option<&mut dyn RngCore>) -> &Array2<f64> {
+        let mut output: Option<Array2<f64>> = None;
+        for layer in self.layers.iter_mut() {
+            let rng = rng.as_mut().map(|rng| &mut **rng as &mut dyn RngCore);
+            output = Some(layer.forward(output.as_ref().unwrap_or(input), rng));
+        }
+        self.weighted_inputs = output.unwrap_or_else(|| input.clone());
+        self.activations = self.output_activation.activate(&self.weighted_inputs);
+        &self.activations
+    }
+
+    //Backpropagates the last forward against the targets, one column per image, adding to every layer's parameter gradients
+    pub fn backward(&mut self, cost: &dyn Cost, targets: &Array2<f64>) {
+        let mut delta = cost.delta(&self.activations, targets, &self.weighted_inputs, self.output_activation);
+        let first_learning_layer = self.first_learning_layer;
+        for (index, layer) in self.layers.iter_mut().enumerate().skip(first_learning_layer).rev() {
+            delta = layer.backward(delta, index > first_learning_layer);
+        }
+    }
+
+    //Of every layer that learns, in order
+    pub fn parameters(&mut self) -> Vec<LayerParameters<'_>> {
+        self.layers.iter_mut().filter_map(|layer| layer.parameters()).collect()
+    }
+
+    //Of every layer whose weights are regularized, in order
+    pub fn weights(&self) -> Vec<&Array2<f64>> {
+        self.layers.iter().filter_map(|layer| layer.weights()).collect()
+    }
+
+    //One entry per layer, empty for those with nothing to save
+    pub fn saved(&self) -> Vec<Vec<Array2<f64>>> {
+        self.layers.iter().map(|layer| layer.saved()).collect()
+    }
+
+    pub fn restore(&mut self, saved: Vec<Vec<Array2<f64>>>) {
+        assert_eq!(saved.len(), self.layers.len(), "a saved network has an entry for every layer");
+        for (layer, saved) in self.layers.iter_mut().zip(saved) {
+            if !saved.is_empty() {
+                layer.restore(saved);
+            }
+        }
+    }
+}
+
+//Collects the layers in order, only building them once the output activation is known, when each layer's input shape
+//follows from the ones before it
+pub struct SequentialBuilder {
+    input_shape: FeatureShape,
+    layers: Vec<LayerKind>,
+}
+
+impl SequentialBuilder {
+    pub fn layer(mut self, layer: LayerKind) -> Self {
+        self.layers.push(layer);
+        self
+    }
+
+    pub fn dense(self, neurons: usize) -> Self {
+        self.layer(LayerKind::Dense { neurons })
+    }
+
+    pub fn activation(self, activation: Activation) -> Self {
+        self.layer(LayerKind::Activation(activation))
+    }
+
+    //Nothing is dropped when keep_probability is 1, so no layer is added
+    pub fn dropout(self, keep_probability: f64) -> Self {
+        if keep_probability < 1.0 {
+            self.layer(LayerKind::Dropout { keep_probability })
+        } else {
+            self
+        }
+    }
+
+    pub fn batch_norm(self) -> Self {
+        self.layer(LayerKind::BatchNorm)
+    }
+
+    //Initial parameters are drawn from rng, layer by layer
+    pub fn build(self, output_activation: Activation, initializer: &Initialization, rng: &mut dyn RngCore) -> Result<Sequential, String> {
+        let mut layers = Vec::with_capacity(self.layers.len());
+        let mut shape = self.input_shape;
+        for layer in &self.layers {
+            layers.push(layer.build(shape, initializer, rng)?);
+            shape = layer.output_shape(shape)?;
+        }
+
+        let first_learning_layer = layers.iter_mut().position(|layer| layer.parameters().is_some()).unwrap_or(layers.len());
+        Ok(Sequential {
+            layers,
+            output_activation,
+            first_learning_layer,
+            weighted_inputs: Array2::zeros((0, 0)),
+            activations: Array2::zeros((0, 0)),
+        })
+    }
+}
@@ -0,0 +1,64 @@
+//Options and results of Network::train
+
+use crate::augmentation::Augmentations;
+
+//Which extra metrics to compute after every epoch. Evaluation accuracy is always computed,
+//since early stopping and the plateau schedule are driven by it
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Monitor {
+    pub training_cost: bool,
+    pub training_accuracy: bool,
+    pub evaluation_cost: bool,
+}
+
+pub struct TrainingOptions {
+    pub epochs: usize,
+    pub batch_size: usize,
+    //Stop once this many epochs pass without the evaluation accuracy improving, restoring the best weights
+    pub early_stopping: Option<usize>,
+    pub monitor: Monitor,
+    //Applied to every training image as its batch is drawn
+    pub augmentations: Augmentations,
+}
+
+#[derive(Clone, Debug)]
+pub struct EpochMetrics {
+    pub epoch: usize,
+    pub learning_rate: f64,
+    pub evaluation_accuracy: f64,
+    pub evaluation_cost: Option<f64>,
+    pub training_accuracy: Option<f64>,
+    pub training_cost: Option<f64>,
+}
+
+impl std::fmt::Display for EpochMetrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Epoch {}: {}% (learning rate {})", self.epoch, self.evaluation_accuracy, self.learning_rate)?;
+        if let Some(cost) = self.evaluation_cost {
+            write!(f, ", evaluation cost {}", cost)?;
+        }
+        if let Some(accuracy) = self.training_accuracy {
+            write!(f, ", training accuracy {}%", accuracy)?;
+        }
+        if let Some(cost) = self.training_cost {
+            write!(f, ", training cost {}", cost)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TrainingHistory {
+    pub epochs: Vec<EpochMetrics>,
+    //Set when early stopping restored the weights of an earlier epoch
+    pub restored_epoch: Option<usize>,
+}
+
+impl TrainingHistory {
+    pub fn best_epoch(&self) -> Option<&EpochMetrics> {
+        self.epochs.iter().fold(None, |best: Option<&EpochMetrics>, metrics| match best {
+            Some(best) if best.evaluation_accuracy >= metrics.evaluation_accuracy => Some(best),
+            _ => Some(metrics),
+        })
+    }
+}